@@ -0,0 +1,218 @@
+//! Compares the pool against the scheduler this crate used before the
+//! per-worker queues, and against `futures::executor::ThreadPool`.
+//!
+//! The old scheduler is reproduced by [`OldPool`]: All tasks go through a
+//! single channel, whose receiver sits behind a mutex which the workers
+//! contend on. A worker keeps every task it took, so tasks never move to
+//! an idle worker.
+//!
+//! Run with `cargo bench --target x86_64-unknown-linux-gnu`.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use criterion::{criterion_group, BenchmarkId, Criterion};
+    use futures::channel::mpsc;
+    use futures::executor::{block_on, LocalPool, ThreadPool as FuturesPool};
+    use futures::future::FutureObj;
+    use futures::lock::Mutex;
+    use futures::task::{LocalSpawnExt, Spawn, SpawnError, SpawnExt};
+    use futures::{Future, StreamExt};
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use wasm_futures_executor::ThreadPool;
+
+    const WORKERS: usize = 4;
+    const TASKS: usize = 1000;
+
+    /// The old scheduler: Each worker takes tasks from the shared receiver
+    /// and runs them on its own local executor.
+    #[derive(Clone)]
+    struct OldPool {
+        tx: mpsc::UnboundedSender<FutureObj<'static, ()>>,
+    }
+
+    impl OldPool {
+        fn new(size: usize) -> Self {
+            let (tx, rx) = mpsc::unbounded::<FutureObj<'static, ()>>();
+            let rx = Arc::new(Mutex::new(rx));
+            for _ in 0..size {
+                let rx = rx.clone();
+                // The workers exit once all senders are gone.
+                thread::spawn(move || {
+                    let mut pool = LocalPool::new();
+                    let spawner = pool.spawner();
+                    pool.run_until(async move {
+                        while let Some(task) = rx.lock().await.next().await {
+                            spawner.spawn_local(task).unwrap();
+                        }
+                    });
+                    pool.run();
+                });
+            }
+            Self { tx }
+        }
+    }
+
+    impl Spawn for OldPool {
+        fn spawn_obj(&self, future: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+            self.tx
+                .unbounded_send(future)
+                .map_err(|_| SpawnError::shutdown())
+        }
+    }
+
+    fn pool() -> ThreadPool {
+        block_on(
+            ThreadPool::builder()
+                .pool_size(WORKERS)
+                .queue_capacity(TASKS)
+                .create(),
+        )
+        .unwrap()
+    }
+
+    /// Blocks until all `n` tasks have reported back via `rx`.
+    fn wait_for(rx: mpsc::UnboundedReceiver<usize>, n: usize) {
+        assert_eq!(block_on(rx.collect::<Vec<_>>()).len(), n);
+    }
+
+    /// Spawns `TASKS` tasks from outside the pool.
+    fn flat(c: &mut Criterion) {
+        let mut group = c.benchmark_group("flat");
+        let pool = pool();
+        let old_pool = OldPool::new(WORKERS);
+        let futures_pool = FuturesPool::builder().pool_size(WORKERS).create().unwrap();
+        group.bench_function(BenchmarkId::new("wasm-futures-executor", TASKS), |b| {
+            b.iter(|| run_flat(&pool))
+        });
+        group.bench_function(BenchmarkId::new("old-scheduler", TASKS), |b| {
+            b.iter(|| run_flat(&old_pool))
+        });
+        group.bench_function(BenchmarkId::new("futures-executor", TASKS), |b| {
+            b.iter(|| run_flat(&futures_pool))
+        });
+        group.finish();
+    }
+
+    fn run_flat(spawner: &impl SpawnExt) {
+        let (tx, rx) = mpsc::unbounded();
+        for i in 0..TASKS {
+            let tx = tx.clone();
+            spawner
+                .spawn(async move { tx.unbounded_send(i).unwrap() })
+                .unwrap();
+        }
+        drop(tx);
+        wait_for(rx, TASKS);
+    }
+
+    /// Spawns `WORKERS` tasks, each of which spawns `TASKS / WORKERS` sub-tasks
+    /// from within the pool.
+    fn nested(c: &mut Criterion) {
+        let mut group = c.benchmark_group("nested");
+        let pool = pool();
+        let old_pool = OldPool::new(WORKERS);
+        let futures_pool = FuturesPool::builder().pool_size(WORKERS).create().unwrap();
+        group.bench_function(BenchmarkId::new("wasm-futures-executor", TASKS), |b| {
+            b.iter(|| run_nested(&pool, pool.clone()))
+        });
+        group.bench_function(BenchmarkId::new("old-scheduler", TASKS), |b| {
+            b.iter(|| run_nested(&old_pool, old_pool.clone()))
+        });
+        group.bench_function(BenchmarkId::new("futures-executor", TASKS), |b| {
+            b.iter(|| run_nested(&futures_pool, futures_pool.clone()))
+        });
+        group.finish();
+    }
+
+    fn run_nested<S: SpawnExt + Clone + Send + 'static>(spawner: &S, inner: S) {
+        let (tx, rx) = mpsc::unbounded();
+        for _ in 0..WORKERS {
+            let tx = tx.clone();
+            let inner = inner.clone();
+            spawner
+                .spawn(async move {
+                    for i in 0..TASKS / WORKERS {
+                        let tx = tx.clone();
+                        inner
+                            .spawn(async move { tx.unbounded_send(i).unwrap() })
+                            .unwrap();
+                    }
+                })
+                .unwrap();
+        }
+        drop(tx);
+        wait_for(rx, TASKS);
+    }
+
+    const LONG_TASKS: usize = 32;
+    const ROUNDS: usize = 10;
+
+    /// Spawns `LONG_TASKS` tasks, which alternate between computing and
+    /// yielding for `ROUNDS` rounds. How long a task computes per round
+    /// differs between the tasks, so the load is uneven.
+    fn uneven(c: &mut Criterion) {
+        let mut group = c.benchmark_group("uneven");
+        group.sample_size(10);
+        let pool = pool();
+        let old_pool = OldPool::new(WORKERS);
+        let futures_pool = FuturesPool::builder().pool_size(WORKERS).create().unwrap();
+        group.bench_function(BenchmarkId::new("wasm-futures-executor", LONG_TASKS), |b| {
+            b.iter(|| run_uneven(&pool))
+        });
+        group.bench_function(BenchmarkId::new("old-scheduler", LONG_TASKS), |b| {
+            b.iter(|| run_uneven(&old_pool))
+        });
+        group.bench_function(BenchmarkId::new("futures-executor", LONG_TASKS), |b| {
+            b.iter(|| run_uneven(&futures_pool))
+        });
+        group.finish();
+    }
+
+    fn run_uneven(spawner: &impl SpawnExt) {
+        let (tx, rx) = mpsc::unbounded();
+        for i in 0..LONG_TASKS {
+            let tx = tx.clone();
+            let work = Duration::from_micros(50 * (i % 8) as u64);
+            spawner
+                .spawn(async move {
+                    for _ in 0..ROUNDS {
+                        let start = Instant::now();
+                        while start.elapsed() < work {}
+                        YieldNow(false).await;
+                    }
+                    tx.unbounded_send(i).unwrap();
+                })
+                .unwrap();
+        }
+        drop(tx);
+        wait_for(rx, LONG_TASKS);
+    }
+
+    /// Wakes itself up once before completing, so the task is queued again.
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    criterion_group!(benches, flat, nested, uneven);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+criterion::criterion_main!(native::benches);
+
+#[cfg(target_arch = "wasm32")]
+fn main() {}
@@ -1,7 +1,6 @@
 use std::time::Duration;
 
-use futures::channel::mpsc;
-use futures::StreamExt;
+use futures::future::join_all;
 use instant::Instant;
 use log::*;
 use wasm_bindgen::{prelude::*, JsCast};
@@ -17,22 +16,21 @@ pub fn main() {
 #[wasm_bindgen]
 pub async fn start() -> Result<JsValue, JsValue> {
     let pool = ThreadPool::max_threads().await?;
-    let (tx, mut rx) = mpsc::channel(10);
-    for i in 0..20 {
-        let mut tx_c = tx.clone();
-        pool.spawn_ok(async move {
+    // The closures block their threads, so they're kept away from the
+    // workers running async tasks.
+    let handles = (0..20).map(|i| {
+        pool.spawn_blocking(move || {
             let global = js_sys::global().unchecked_into::<DedicatedWorkerGlobalScope>();
             info!("Task {} running on {}", i, global.name());
             let now = Instant::now();
-            // Block worker
+            // Block thread
             while now.elapsed() < Duration::from_secs(2) {}
-            tx_c.start_send(i * i).unwrap();
-        });
-    }
-    drop(tx);
+            i * i
+        })
+    });
     let mut i = 0;
-    while let Some(x) = rx.next().await {
-        i += x;
+    for x in join_all(handles).await {
+        i += x.map_err(|e| e.to_string())?;
     }
     Ok(i.into())
 }
@@ -3,9 +3,8 @@ use std::time::Duration;
 use futures::channel::mpsc;
 use futures::StreamExt;
 use log::*;
-use wasm_bindgen::{prelude::*, JsCast};
-use wasm_futures_executor::ThreadPool;
-use web_sys::DedicatedWorkerGlobalScope;
+use wasm_bindgen::prelude::*;
+use wasm_futures_executor::{current_worker, ThreadPool};
 
 #[wasm_bindgen(start)]
 pub fn main() {
@@ -19,33 +18,20 @@ pub async fn start() -> Result<JsValue, JsValue> {
     let (tx, mut rx) = mpsc::channel(10);
     for i in 0..5 {
         let mut tx_c = tx.clone();
-        let p_c = pool.clone();
         pool.spawn_ok(async move {
-            let name = js_sys::global()
-                .unchecked_into::<DedicatedWorkerGlobalScope>()
-                .name()
-                .as_str()
-                .to_string();
+            let name = current_worker().unwrap().name;
             info!("Task {} running on {}", i, name);
             let mut x = 0;
             for j in 0..20 {
-                let p_c = p_c.clone();
                 x += async move {
-                    let name = js_sys::global()
-                        .unchecked_into::<DedicatedWorkerGlobalScope>()
-                        .name()
-                        .as_str()
-                        .to_string();
+                    let name = current_worker().unwrap().name;
                     info!("Task {}-{} running on {}", i, j, name);
+                    let pool = ThreadPool::current().expect("running on a pool worker");
                     let mut y = 0;
                     for k in 0..3 {
-                        y += p_c
+                        y += pool
                             .spawn(async move {
-                                let name = js_sys::global()
-                                    .unchecked_into::<DedicatedWorkerGlobalScope>()
-                                    .name()
-                                    .as_str()
-                                    .to_string();
+                                let name = current_worker().unwrap().name;
                                 info!("Task {}-{}-{} running on {}", i, j, k, name);
                                 k
                             })
@@ -67,3 +53,39 @@ pub async fn start() -> Result<JsValue, JsValue> {
     }
     Ok(i.into())
 }
+
+/// Hammers the pool with spawns from the main thread and from within the
+/// workers concurrently, and checks that every task ran exactly once.
+#[wasm_bindgen]
+pub async fn stress() -> Result<JsValue, JsValue> {
+    const OUTER: usize = 500;
+    const INNER: usize = 10;
+    let pool = ThreadPool::builder()
+        .pool_size(8)
+        .queue_capacity(256)
+        .create()
+        .await?;
+    let (tx, rx) = mpsc::unbounded();
+    for _ in 0..OUTER {
+        let tx_c = tx.clone();
+        let p_c = pool.clone();
+        pool.spawn_async(async move {
+            for _ in 0..INNER {
+                let tx_c = tx_c.clone();
+                p_c.spawn_async(async move {
+                    tx_c.unbounded_send(()).unwrap();
+                })
+                .await
+                .unwrap();
+            }
+        })
+        .await
+        .map_err(|e| JsValue::from(e.to_string()))?;
+    }
+    drop(tx);
+    let count = rx.count().await;
+    if count != OUTER * INNER {
+        return Err(format!("Expected {} tasks, got {}", OUTER * INNER, count).into());
+    }
+    Ok((count as u32).into())
+}
@@ -0,0 +1,152 @@
+//! The threads running the closures passed to [`ThreadPool::spawn_blocking`].
+//!
+//! [`ThreadPool::spawn_blocking`]: crate::ThreadPool::spawn_blocking
+
+use crossbeam_deque::{Injector, Steal};
+use futures::FutureExt;
+use std::iter;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+use crate::join::TaskState;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::native::Launcher;
+use crate::pool::Task;
+#[cfg(target_arch = "wasm32")]
+use crate::worker::Launcher;
+use crate::{JoinError, ThreadPoolBuilder};
+
+struct Job {
+    task: Task,
+    state: Arc<TaskState>,
+}
+
+/// The job a blocking thread is running, so it can be failed if the thread
+/// crashes.
+type Running = Mutex<Option<Arc<TaskState>>>;
+
+/// An elastic set of threads, separate from the workers of a pool. Threads
+/// are started on demand up to `max_threads`, and exit after having been idle
+/// for `keep_alive`.
+pub(crate) struct BlockingPool {
+    queue: Injector<Job>,
+    /// Threads which are about to park, waiting for jobs. Might contain
+    /// threads which are busy again; waking those is harmless.
+    sleepers: Injector<Thread>,
+    /// Number of parked threads.
+    idle: AtomicUsize,
+    /// Number of started threads, which haven't exited yet.
+    threads: AtomicUsize,
+    max_threads: usize,
+    keep_alive: Duration,
+    next_id: AtomicUsize,
+    launcher: Arc<Launcher>,
+}
+
+impl BlockingPool {
+    pub(crate) fn new(builder: &ThreadPoolBuilder, launcher: Arc<Launcher>) -> Arc<Self> {
+        Arc::new(Self {
+            queue: Injector::new(),
+            sleepers: Injector::new(),
+            idle: AtomicUsize::new(0),
+            threads: AtomicUsize::new(0),
+            max_threads: builder.max_blocking_threads,
+            keep_alive: builder.blocking_keep_alive,
+            next_id: AtomicUsize::new(0),
+            launcher,
+        })
+    }
+
+    /// Queues `task`, and starts another thread if none is idle. This never
+    /// blocks, so it's fine to call from the browser's main thread.
+    pub(crate) fn spawn(self: &Arc<Self>, task: Task, state: Arc<TaskState>) {
+        self.queue.push(Job { task, state });
+        let idle = self.idle.load(Ordering::SeqCst);
+        loop {
+            match self.sleepers.steal() {
+                Steal::Success(thread) => thread.unpark(),
+                Steal::Retry => continue,
+                Steal::Empty => break,
+            }
+        }
+        if idle == 0 {
+            self.start_thread();
+        }
+    }
+
+    /// Drops all queued jobs, failing them with `err`.
+    pub(crate) fn clear(&self, err: JoinError) {
+        while let Some(job) = self.pop() {
+            job.state.fail(err);
+        }
+    }
+
+    fn pop(&self) -> Option<Job> {
+        iter::repeat_with(|| self.queue.steal())
+            .find(|s| !s.is_retry())
+            .and_then(|s| s.success())
+    }
+
+    fn start_thread(self: &Arc<Self>) {
+        if self.threads.fetch_add(1, Ordering::SeqCst) >= self.max_threads {
+            // The queued job is picked up once one of the threads is done.
+            self.threads.fetch_sub(1, Ordering::SeqCst);
+            return;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let name = format!("{}blocking-{}", self.launcher.name_prefix, id);
+        let running = Arc::new(Running::new(None));
+        let (pool, r) = (self.clone(), running.clone());
+        let run = Box::pin(async move { pool.run(&r) });
+        let pool = self.clone();
+        let on_error = Box::new(move |err| pool.thread_failed(&running, err));
+        self.launcher.spawn(name, run, on_error);
+    }
+
+    /// Runs queued jobs on the current thread, until there were none for
+    /// `keep_alive`.
+    fn run(&self, running: &Running) {
+        loop {
+            if let Some(Job { task, state }) = self.pop() {
+                *running.lock().unwrap() = Some(state);
+                // Completes right away, as the task just calls the closure.
+                // Not using `block_on`, as the closure might do so itself.
+                let _ = task.now_or_never();
+                running.lock().unwrap().take();
+                continue;
+            }
+            self.sleepers.push(thread::current());
+            // Don't miss a job queued before registering.
+            if !self.queue.is_empty() {
+                continue;
+            }
+            self.idle.fetch_add(1, Ordering::SeqCst);
+            thread::park_timeout(self.keep_alive);
+            self.idle.fetch_sub(1, Ordering::SeqCst);
+            if self.queue.is_empty() {
+                self.threads.fetch_sub(1, Ordering::SeqCst);
+                // A job queued meanwhile might rely on this thread, as it
+                // was still counted as idle.
+                if self.queue.is_empty() {
+                    return;
+                }
+                self.threads.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Called if a thread couldn't be started, crashed or was terminated.
+    /// The job it was running, if any, is failed with `err`.
+    fn thread_failed(&self, running: &Running, err: JoinError) {
+        // Don't block, as this might run on the main thread.
+        if let Some(state) = running.try_lock().ok().and_then(|mut r| r.take()) {
+            state.fail(err);
+        }
+        if self.threads.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // There's no thread left to run the queued jobs.
+            self.clear(err);
+        }
+    }
+}
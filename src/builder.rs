@@ -0,0 +1,292 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use web_sys::RequestCredentials;
+
+use crate::pool::ThreadPool;
+use crate::{PoolError, WorkerError, WorkerInfo};
+
+/// Whether crashed workers of a [`ThreadPool`] are replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Crashed workers are not replaced, reducing the capacity of the pool.
+    Never,
+    /// Crashed workers are always replaced.
+    Always,
+    /// Crashed workers are replaced, unless there were already the given
+    /// number of restarts within the given time window.
+    MaxRestarts(usize, Duration),
+}
+
+/// Times of recent restarts of a pool's workers, used for
+/// [`RestartPolicy::MaxRestarts`].
+#[derive(Default)]
+pub(crate) struct RestartHistory(VecDeque<Duration>);
+
+impl RestartHistory {
+    /// Checks whether another restart is permitted by `policy`, and records it
+    /// if so. `now` is the current time, measured from any fixed point.
+    pub(crate) fn may_restart(&mut self, policy: RestartPolicy, now: Duration) -> bool {
+        match policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::Always => true,
+            RestartPolicy::MaxRestarts(max, window) => {
+                while matches!(self.0.front(), Some(t) if now.saturating_sub(*t) > window) {
+                    self.0.pop_front();
+                }
+                if self.0.len() < max {
+                    self.0.push_back(now);
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
+
+/// Whether a [`ThreadPool`] may run its tasks on the current thread instead
+/// of on web workers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fallback {
+    /// Creating the pool fails, if web workers with shared memory are not
+    /// available.
+    Never,
+    /// Tasks are run on the current thread, if web workers with shared memory
+    /// are not available, for example because the document is not
+    /// cross-origin isolated.
+    Auto,
+    /// Tasks are always run on the current thread.
+    Always,
+}
+
+/// A builder for configuring and creating a [`ThreadPool`].
+///
+/// The API follows [`futures_executor::ThreadPoolBuilder`].
+///
+/// [`futures_executor::ThreadPoolBuilder`]: https://docs.rs/futures-executor/0.3.16/futures_executor/struct.ThreadPoolBuilder.html
+pub struct ThreadPoolBuilder {
+    pub(crate) pool_size: Option<usize>,
+    pub(crate) name_prefix: String,
+    pub(crate) queue_capacity: usize,
+    pub(crate) credentials: Option<RequestCredentials>,
+    pub(crate) on_worker_error: Option<Arc<dyn Fn(WorkerError) + Send + Sync>>,
+    pub(crate) restart_policy: RestartPolicy,
+    pub(crate) startup_timeout: Duration,
+    pub(crate) unresponsive_timeout: Option<Duration>,
+    pub(crate) fallback: Fallback,
+    pub(crate) after_start: Option<Arc<dyn Fn(WorkerInfo) + Send + Sync>>,
+    pub(crate) before_stop: Option<Arc<dyn Fn(WorkerInfo) + Send + Sync>>,
+    pub(crate) max_blocking_threads: usize,
+    pub(crate) blocking_keep_alive: Duration,
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ThreadPoolBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadPoolBuilder")
+            .field("pool_size", &self.pool_size)
+            .field("name_prefix", &self.name_prefix)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("credentials", &self.credentials)
+            .field("restart_policy", &self.restart_policy)
+            .field("startup_timeout", &self.startup_timeout)
+            .field("unresponsive_timeout", &self.unresponsive_timeout)
+            .field("fallback", &self.fallback)
+            .field("max_blocking_threads", &self.max_blocking_threads)
+            .field("blocking_keep_alive", &self.blocking_keep_alive)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ThreadPoolBuilder {
+    /// Create a default thread pool configuration.
+    ///
+    /// See the other methods on this type for details on the defaults.
+    pub fn new() -> Self {
+        Self {
+            pool_size: None,
+            name_prefix: "Worker-".into(),
+            queue_capacity: 64,
+            credentials: None,
+            on_worker_error: None,
+            restart_policy: RestartPolicy::Never,
+            startup_timeout: Duration::from_secs(30),
+            unresponsive_timeout: None,
+            fallback: Fallback::Never,
+            after_start: None,
+            before_stop: None,
+            max_blocking_threads: 16,
+            blocking_keep_alive: Duration::from_secs(10),
+        }
+    }
+
+    /// Set size of a future [`ThreadPool`].
+    ///
+    /// The size of a thread pool is the number of web workers spawned. By
+    /// default, this is equal to `Navigator.hardwareConcurrency`, or the
+    /// number of logical CPUs on native targets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pool_size == 0`.
+    pub fn pool_size(&mut self, size: usize) -> &mut Self {
+        assert!(size > 0, "there must be at least one worker");
+        self.pool_size = Some(size);
+        self
+    }
+
+    /// Set worker name prefix of a future [`ThreadPool`].
+    ///
+    /// Worker name prefix is used for generating web worker names (as shown
+    /// in the browser's devtools). For example, if prefix is `my-pool-`, then
+    /// workers in the pool will get names like `my-pool-0` etc. By default,
+    /// the prefix is `Worker-`.
+    pub fn name_prefix<S: Into<String>>(&mut self, name_prefix: S) -> &mut Self {
+        self.name_prefix = name_prefix.into();
+        self
+    }
+
+    /// Set the number of tasks, which can be queued up in a future
+    /// [`ThreadPool`] before being picked up by a worker. By default, this is
+    /// 64.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity == 0`.
+    pub fn queue_capacity(&mut self, capacity: usize) -> &mut Self {
+        assert!(capacity > 0, "the queue capacity must be at least one");
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Set the credentials passed via `WorkerOptions` to the web workers
+    /// of a future [`ThreadPool`]. This is relevant if the worker script is
+    /// loaded from a different origin. If not set, the browser's default is
+    /// used. This has no effect on native targets.
+    pub fn credentials(&mut self, credentials: RequestCredentials) -> &mut Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Execute the closure `f` whenever a worker of a future [`ThreadPool`]
+    /// crashes, for example because of a panic or an uncaught exception, or
+    /// is stuck (see [`ThreadPoolBuilder::unresponsive_timeout`]). The
+    /// crashed worker is terminated, and the task it was polling fails with
+    /// [`JoinError::WorkerDied`]. Other tasks continue on the remaining
+    /// workers.
+    ///
+    /// The closure is called on the thread which created the pool, or on the
+    /// crashed worker thread on native targets.
+    ///
+    /// [`JoinError::WorkerDied`]: crate::JoinError::WorkerDied
+    pub fn on_worker_error<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(WorkerError) + Send + Sync + 'static,
+    {
+        self.on_worker_error = Some(Arc::new(f));
+        self
+    }
+
+    /// Set whether crashed workers of a future [`ThreadPool`] are replaced
+    /// by fresh ones with the same name. By default, they are not
+    /// ([`RestartPolicy::Never`]).
+    pub fn restart_policy(&mut self, policy: RestartPolicy) -> &mut Self {
+        self.restart_policy = policy;
+        self
+    }
+
+    /// Set the time a worker of a future [`ThreadPool`] may take to load
+    /// and initialize the wasm module. If any worker takes longer, creating
+    /// the pool fails with [`PoolError::StartupTimeout`]. By default, this is
+    /// 30 seconds.
+    pub fn startup_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.startup_timeout = timeout;
+        self
+    }
+
+    /// Treat a worker of a future [`ThreadPool`] which has been stuck in a
+    /// single poll of a task for longer than `timeout` as crashed: it is
+    /// terminated, the task fails with [`JoinError::WorkerDied`], and the
+    /// worker is replaced according to the [`RestartPolicy`]. Stuck workers
+    /// are noticed within twice the `timeout`. By default, workers are never
+    /// considered stuck, as tasks may legitimately block for a long time.
+    ///
+    /// This has no effect on native targets, as threads can't be killed, and
+    /// on pools running on the current thread (see [`Fallback`]).
+    ///
+    /// [`JoinError::WorkerDied`]: crate::JoinError::WorkerDied
+    pub fn unresponsive_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.unresponsive_timeout = Some(timeout);
+        self
+    }
+
+    /// Set whether a future [`ThreadPool`] may run its tasks on the current
+    /// thread via `wasm_bindgen_futures::spawn_local` instead of on web
+    /// workers. The API of the pool stays the same, but long running tasks
+    /// will block the current thread. By default, this is
+    /// [`Fallback::Never`].
+    ///
+    /// On native targets, a single worker thread is used instead.
+    pub fn fallback(&mut self, fallback: Fallback) -> &mut Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Execute the closure `f` on each worker of a future [`ThreadPool`]
+    /// right after it started, before it runs any tasks. This is the place to
+    /// set up per-worker state like loggers, panic hooks, or thread-local
+    /// caches. The closure is also called for restarted workers.
+    pub fn after_start<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(WorkerInfo) + Send + Sync + 'static,
+    {
+        self.after_start = Some(Arc::new(f));
+        self
+    }
+
+    /// Execute the closure `f` on each worker of a future [`ThreadPool`]
+    /// right before it shuts down, after it finished all its tasks. The
+    /// closure is not called for workers which crashed or were terminated.
+    pub fn before_stop<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(WorkerInfo) + Send + Sync + 'static,
+    {
+        self.before_stop = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the maximum number of threads a future [`ThreadPool`] starts for
+    /// [`ThreadPool::spawn_blocking`], in addition to its workers. These
+    /// threads are started on demand. By default, this is 16.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max == 0`.
+    pub fn max_blocking_threads(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "there must be at least one blocking thread");
+        self.max_blocking_threads = max;
+        self
+    }
+
+    /// Set how long a thread started for [`ThreadPool::spawn_blocking`] waits
+    /// for further work before exiting. By default, this is 10 seconds.
+    pub fn blocking_keep_alive(&mut self, keep_alive: Duration) -> &mut Self {
+        self.blocking_keep_alive = keep_alive;
+        self
+    }
+
+    /// Create a [`ThreadPool`] with the given configuration. The returned
+    /// future will resolve after all workers have spawned and are ready to
+    /// accept work. Workers are started concurrently.
+    pub async fn create(&mut self) -> Result<ThreadPool, PoolError> {
+        ThreadPool::create(self).await
+    }
+}
@@ -0,0 +1,158 @@
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+use crate::PoolError;
+
+/// Capabilities of the current environment relevant for creating a
+/// [`ThreadPool`], as returned by [`check_environment`].
+///
+/// [`ThreadPool`]: crate::ThreadPool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Environment {
+    /// Whether the document is cross-origin isolated (`crossOriginIsolated`).
+    /// This is also `true` for browsers which don't require cross-origin
+    /// isolation for sharing memory.
+    pub cross_origin_isolated: bool,
+    /// Whether `SharedArrayBuffer` is available.
+    pub shared_array_buffer: bool,
+    /// Whether web workers of type `module` can be created. Always `true` on
+    /// Node.js, where `worker_threads` are used.
+    pub module_workers: bool,
+    /// `Navigator.hardwareConcurrency`, but at least 1. On Node.js versions
+    /// without `navigator`, this is only known after a `ThreadPool` was
+    /// created. On native targets, the number of logical CPUs.
+    pub hardware_concurrency: usize,
+}
+
+impl Environment {
+    /// Returns an error explaining why a [`ThreadPool`] can't be created in
+    /// this environment, if any.
+    ///
+    /// [`ThreadPool`]: crate::ThreadPool
+    pub fn check(&self) -> Result<(), PoolError> {
+        if !self.cross_origin_isolated {
+            Err(PoolError::NotCrossOriginIsolated)
+        } else if !self.shared_array_buffer {
+            Err(PoolError::SharedMemoryUnavailable)
+        } else if !self.module_workers {
+            Err(PoolError::ModuleWorkersUnsupported)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(module = "/worker.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = "supportsModuleWorkers")]
+    fn supports_module_workers() -> bool;
+
+    #[wasm_bindgen(js_name = "hardwareConcurrency")]
+    fn js_hardware_concurrency() -> usize;
+
+    #[wasm_bindgen(js_name = "loadHardwareConcurrency")]
+    /// Returns Promise<void>
+    fn js_load_hardware_concurrency() -> js_sys::Promise;
+}
+
+/// Checks the capabilities of the current environment, for example to
+/// decide upfront whether to create a [`ThreadPool`] at all. Creating a
+/// pool performs the same checks.
+///
+/// [`ThreadPool`]: crate::ThreadPool
+#[cfg(target_arch = "wasm32")]
+pub fn check_environment() -> Environment {
+    Environment {
+        cross_origin_isolated: cross_origin_isolated(),
+        shared_array_buffer: has_global("SharedArrayBuffer"),
+        module_workers: supports_module_workers(),
+        hardware_concurrency: hardware_concurrency(),
+    }
+}
+
+/// Checks the capabilities of the current environment. On native targets,
+/// threads with shared memory are always available.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn check_environment() -> Environment {
+    Environment {
+        cross_origin_isolated: true,
+        shared_array_buffer: true,
+        module_workers: true,
+        hardware_concurrency: hardware_concurrency(),
+    }
+}
+
+/// Returns whether the current thread is a worker of a [`ThreadPool`].
+///
+/// Workers instantiate the wasm module again, which runs the
+/// `#[wasm_bindgen(start)]` function on every worker. Unlike
+/// [`current_worker`], this already works during that time, so code which
+/// must only run on the main thread can be skipped:
+///
+/// ```ignore
+/// #[wasm_bindgen(start)]
+/// pub fn main() {
+///     if wasm_futures_executor::is_pool_worker() {
+///         return;
+///     }
+///     // Main thread only, e.g. touching `document`.
+/// }
+/// ```
+///
+/// [`ThreadPool`]: crate::ThreadPool
+/// [`current_worker`]: crate::current_worker
+#[cfg(target_arch = "wasm32")]
+pub fn is_pool_worker() -> bool {
+    crate::current_worker().is_some()
+        || js_sys::Reflect::get(&js_sys::global(), &"wasmFuturesExecutorWorker".into())
+            .ok()
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+}
+
+/// Returns whether the current thread is a worker of a [`ThreadPool`].
+///
+/// [`ThreadPool`]: crate::ThreadPool
+#[cfg(not(target_arch = "wasm32"))]
+pub fn is_pool_worker() -> bool {
+    crate::current_worker().is_some()
+}
+
+/// Returns `Navigator.hardwareConcurrency` (or its equivalent on Node.js),
+/// but at least 1.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn hardware_concurrency() -> usize {
+    std::cmp::max(js_hardware_concurrency(), 1)
+}
+
+/// Returns the number of logical CPUs.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn hardware_concurrency() -> usize {
+    num_cpus::get()
+}
+
+/// Makes sure [`hardware_concurrency`] knows the number of CPUs, which
+/// needs an asynchronous import on Node.js versions without `navigator`.
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn load_hardware_concurrency() {
+    let _ = wasm_bindgen_futures::JsFuture::from(js_load_hardware_concurrency()).await;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn load_hardware_concurrency() {}
+
+/// Returns `crossOriginIsolated`. Browsers which don't know about this
+/// property don't require cross-origin isolation for shared memory.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn cross_origin_isolated() -> bool {
+    js_sys::Reflect::get(&js_sys::global(), &"crossOriginIsolated".into())
+        .ok()
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn has_global(name: &str) -> bool {
+    js_sys::Reflect::has(&js_sys::global(), &name.into()).unwrap_or(false)
+}
@@ -0,0 +1,215 @@
+use std::fmt;
+
+use wasm_bindgen::JsValue;
+
+/// The error returned when a task could not be spawned onto a [`ThreadPool`].
+///
+/// [`ThreadPool`]: crate::ThreadPool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// The task queue of the pool is at capacity. Retry later or use
+    /// [`ThreadPool::spawn_async`] to wait for free capacity.
+    ///
+    /// [`ThreadPool::spawn_async`]: crate::ThreadPool::spawn_async
+    Full,
+    /// The pool has been shut down and doesn't accept new tasks anymore.
+    Shutdown,
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::Full => write!(f, "task queue is full"),
+            SpawnError::Shutdown => write!(f, "thread pool is shut down"),
+        }
+    }
+}
+
+impl std::error::Error for SpawnError {}
+
+/// The error returned by a [`JoinHandle`], if the task didn't run to
+/// completion.
+///
+/// [`JoinHandle`]: crate::JoinHandle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was cancelled via [`JoinHandle::abort`].
+    ///
+    /// [`JoinHandle::abort`]: crate::JoinHandle::abort
+    Cancelled,
+    /// The task panicked. Note that with `panic=abort` (the default on
+    /// `wasm32-unknown-unknown`) a panic takes down the whole worker instead,
+    /// which is reported as [`JoinError::WorkerDied`].
+    Panicked,
+    /// The worker running the task died.
+    WorkerDied,
+    /// The pool was shut down before the task completed.
+    PoolShutdown,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => write!(f, "task was cancelled"),
+            JoinError::Panicked => write!(f, "task panicked"),
+            JoinError::WorkerDied => write!(f, "worker running the task died"),
+            JoinError::PoolShutdown => write!(f, "thread pool was shut down"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+/// Details about a worker which crashed, as reported by its `error` event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerError {
+    /// Index of the worker within its pool.
+    pub index: usize,
+    /// Name of the worker.
+    pub name: String,
+    /// The error message.
+    pub message: String,
+    /// The script in which the error occurred.
+    pub filename: String,
+    /// The line number in `filename`.
+    pub lineno: u32,
+    /// The column number in `filename`.
+    pub colno: u32,
+}
+
+impl fmt::Display for WorkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} died: {} ({}:{}:{})",
+            self.name, self.message, self.filename, self.lineno, self.colno
+        )
+    }
+}
+
+impl std::error::Error for WorkerError {}
+
+/// The error returned when a [`ThreadPool`] could not be created.
+///
+/// [`ThreadPool`]: crate::ThreadPool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PoolError {
+    /// The document is not cross-origin isolated, so the wasm memory can't be
+    /// shared with web workers. Make sure to send the
+    /// `Cross-Origin-Embedder-Policy: require-corp` and
+    /// `Cross-Origin-Opener-Policy: same-origin` headers.
+    NotCrossOriginIsolated,
+    /// The wasm memory couldn't be shared with web workers, although the
+    /// document is cross-origin isolated. Most likely, the browser doesn't
+    /// support `SharedArrayBuffer`.
+    SharedMemoryUnavailable,
+    /// The browser doesn't support web workers of type `module`. Consider
+    /// using a polyfill like
+    /// [module-workers-polyfill](https://unpkg.com/module-workers-polyfill).
+    ModuleWorkersUnsupported,
+    /// A web worker couldn't load a script, either the worker script itself
+    /// or the main module.
+    WorkerScriptLoadFailed {
+        /// The url of the script.
+        url: String,
+        /// The reported error.
+        message: String,
+    },
+    /// A worker didn't become ready within the startup timeout, see
+    /// [`ThreadPoolBuilder::startup_timeout`].
+    ///
+    /// [`ThreadPoolBuilder::startup_timeout`]: crate::ThreadPoolBuilder::startup_timeout
+    StartupTimeout,
+    /// A worker failed to initialize the wasm module or to enter the pool.
+    WorkerInitFailed(String),
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::NotCrossOriginIsolated => write!(
+                f,
+                "document is not cross-origin isolated, check the COOP and COEP headers"
+            ),
+            PoolError::SharedMemoryUnavailable => write!(f, "shared memory is not available"),
+            PoolError::ModuleWorkersUnsupported => write!(f, "module workers are not supported"),
+            PoolError::WorkerScriptLoadFailed { url, message } => {
+                write!(f, "worker failed to load {}: {}", url, message)
+            }
+            PoolError::StartupTimeout => write!(f, "worker didn't start in time"),
+            PoolError::WorkerInitFailed(msg) => write!(f, "worker failed to initialize: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
+impl From<PoolError> for JsValue {
+    fn from(err: PoolError) -> Self {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
+
+/// The error returned by the functions operating on the global pool, see
+/// [`init_global`].
+///
+/// [`init_global`]: crate::init_global
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GlobalError {
+    /// The global pool hasn't been created yet via [`init_global`].
+    ///
+    /// [`init_global`]: crate::init_global
+    Uninitialized,
+    /// [`init_global`] was called more than once.
+    ///
+    /// [`init_global`]: crate::init_global
+    AlreadyInitialized,
+    /// The global pool couldn't be created.
+    Create(PoolError),
+    /// The task couldn't be spawned onto the global pool.
+    Spawn(SpawnError),
+}
+
+impl fmt::Display for GlobalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GlobalError::Uninitialized => write!(
+                f,
+                "global thread pool is not initialized, call `init_global` first"
+            ),
+            GlobalError::AlreadyInitialized => {
+                write!(f, "global thread pool is already initialized")
+            }
+            GlobalError::Create(err) => write!(f, "{}", err),
+            GlobalError::Spawn(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for GlobalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GlobalError::Create(err) => Some(err),
+            GlobalError::Spawn(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<PoolError> for GlobalError {
+    fn from(err: PoolError) -> Self {
+        GlobalError::Create(err)
+    }
+}
+
+impl From<SpawnError> for GlobalError {
+    fn from(err: SpawnError) -> Self {
+        GlobalError::Spawn(err)
+    }
+}
+
+impl From<GlobalError> for JsValue {
+    fn from(err: GlobalError) -> Self {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
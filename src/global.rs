@@ -0,0 +1,103 @@
+use futures::Future;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::{GlobalError, JoinHandle, ThreadPool, ThreadPoolBuilder};
+
+/// The global pool, leaked once initialized. As statics live in the shared
+/// wasm memory, it's visible to all workers.
+static GLOBAL: AtomicPtr<ThreadPool> = AtomicPtr::new(ptr::null_mut());
+
+/// Creates the global [`ThreadPool`] with the given configuration, which is
+/// then used by [`spawn`], [`spawn_ok`] and their `try_` variants. The
+/// global pool lives for the remaining lifetime of the program.
+///
+/// Fails with [`GlobalError::AlreadyInitialized`], if this was called before,
+/// or with [`GlobalError::Create`], if the pool couldn't be created.
+pub async fn init_global(builder: &mut ThreadPoolBuilder) -> Result<(), GlobalError> {
+    if !GLOBAL.load(Ordering::SeqCst).is_null() {
+        return Err(GlobalError::AlreadyInitialized);
+    }
+    let pool = Box::into_raw(Box::new(builder.create().await?));
+    if GLOBAL
+        .compare_exchange(ptr::null_mut(), pool, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        // Another call won the race.
+        drop(unsafe { Box::from_raw(pool) });
+        return Err(GlobalError::AlreadyInitialized);
+    }
+    Ok(())
+}
+
+/// Returns the global [`ThreadPool`], or [`GlobalError::Uninitialized`] if
+/// [`init_global`] wasn't called before.
+fn try_global() -> Result<&'static ThreadPool, GlobalError> {
+    let pool = GLOBAL.load(Ordering::SeqCst);
+    if pool.is_null() {
+        Err(GlobalError::Uninitialized)
+    } else {
+        Ok(unsafe { &*pool })
+    }
+}
+
+/// Returns the global [`ThreadPool`].
+///
+/// # Panics
+///
+/// Panics if [`init_global`] wasn't called before.
+fn global() -> &'static ThreadPool {
+    match try_global() {
+        Ok(pool) => pool,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Spawns a task onto the global pool, see [`ThreadPool::spawn`].
+///
+/// # Panics
+///
+/// Panics if [`init_global`] wasn't called before, if the task queue is full,
+/// or if the pool has been shut down. Use [`try_spawn`] if that's a concern,
+/// as a panic aborts the whole module on wasm.
+pub fn spawn<Fut>(future: Fut) -> JoinHandle<Fut::Output>
+where
+    Fut: Future + Send + 'static,
+    Fut::Output: Send + 'static,
+{
+    global().spawn(future)
+}
+
+/// Spawns a task onto the global pool, see [`ThreadPool::spawn_ok`].
+///
+/// # Panics
+///
+/// Panics if [`init_global`] wasn't called before, if the task queue is full,
+/// or if the pool has been shut down. Use [`try_spawn_ok`] if that's a
+/// concern, as a panic aborts the whole module on wasm.
+pub fn spawn_ok<Fut>(future: Fut)
+where
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    global().spawn_ok(future)
+}
+
+/// Spawns a task onto the global pool, see [`ThreadPool::try_spawn`]. Fails
+/// with [`GlobalError::Uninitialized`] if [`init_global`] wasn't called before.
+pub fn try_spawn<Fut>(future: Fut) -> Result<JoinHandle<Fut::Output>, GlobalError>
+where
+    Fut: Future + Send + 'static,
+    Fut::Output: Send + 'static,
+{
+    Ok(try_global()?.try_spawn(future)?)
+}
+
+/// Spawns a task onto the global pool, see [`ThreadPool::try_spawn_ok`].
+/// Fails with [`GlobalError::Uninitialized`] if [`init_global`] wasn't called
+/// before.
+pub fn try_spawn_ok<Fut>(future: Fut) -> Result<(), GlobalError>
+where
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Ok(try_global()?.try_spawn_ok(future)?)
+}
@@ -0,0 +1,188 @@
+use futures::channel::oneshot;
+use futures::future::poll_fn;
+use futures::task::AtomicWaker;
+use futures::{Future, FutureExt};
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::pool::track_task;
+use crate::JoinError;
+
+/// A handle to a task spawned onto a [`ThreadPool`]. Awaiting it yields the
+/// output of the task.
+///
+/// Dropping the handle detaches the task; it will still run to completion.
+///
+/// [`ThreadPool`]: crate::ThreadPool
+pub struct JoinHandle<T> {
+    rx: oneshot::Receiver<Result<T, JoinError>>,
+    state: Arc<TaskState>,
+}
+
+pub(crate) struct TaskState {
+    aborted: AtomicBool,
+    finished: AtomicBool,
+    /// Waker of the task itself, woken on `abort`.
+    waker: AtomicWaker,
+    /// Error set from outside of the task, if the task can't report back by
+    /// itself anymore, see [`TaskState::fail`].
+    error: AtomicU8,
+    /// Waker of the [`JoinHandle`], woken on `fail`.
+    join_waker: AtomicWaker,
+}
+
+impl TaskState {
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+
+    /// Fails the task with `err`, for example because the worker polling it
+    /// died.
+    pub(crate) fn fail(&self, err: JoinError) {
+        if !self.finished.swap(true, Ordering::SeqCst) {
+            self.error.store(encode(err), Ordering::SeqCst);
+            self.join_waker.wake();
+        }
+    }
+
+    fn error(&self) -> Option<JoinError> {
+        decode(self.error.load(Ordering::SeqCst))
+    }
+}
+
+fn encode(err: JoinError) -> u8 {
+    match err {
+        JoinError::Cancelled => 1,
+        JoinError::Panicked => 2,
+        JoinError::WorkerDied => 3,
+        JoinError::PoolShutdown => 4,
+    }
+}
+
+fn decode(err: u8) -> Option<JoinError> {
+    match err {
+        1 => Some(JoinError::Cancelled),
+        2 => Some(JoinError::Panicked),
+        3 => Some(JoinError::WorkerDied),
+        4 => Some(JoinError::PoolShutdown),
+        _ => None,
+    }
+}
+
+/// Marks the task as finished, also if it is dropped before completion.
+struct FinishGuard<T> {
+    state: Arc<TaskState>,
+    tx: Option<oneshot::Sender<Result<T, JoinError>>>,
+}
+
+impl<T> FinishGuard<T> {
+    fn finish(mut self, res: Result<T, JoinError>) {
+        self.state.finished.store(true, Ordering::SeqCst);
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(res);
+        }
+    }
+}
+
+impl<T> Drop for FinishGuard<T> {
+    fn drop(&mut self) {
+        if self.tx.is_some() && std::thread::panicking() {
+            // The panic escaped the task, and takes down the worker. Fail
+            // the task before the handle sees the sender dropped.
+            self.state.fail(JoinError::WorkerDied);
+        } else {
+            self.state.finished.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<T: Send + 'static> JoinHandle<T> {
+    /// Wraps `future` into a task to be spawned, which reports its outcome
+    /// to the returned handle.
+    pub(crate) fn new<Fut>(future: Fut) -> (Self, impl Future<Output = ()> + Send + 'static)
+    where
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let state = Arc::new(TaskState {
+            aborted: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+            error: AtomicU8::new(0),
+            join_waker: AtomicWaker::new(),
+        });
+        let guard = FinishGuard {
+            state: state.clone(),
+            tx: Some(tx),
+        };
+        let task = async move {
+            let future = AssertUnwindSafe(future).catch_unwind();
+            futures::pin_mut!(future);
+            let res = poll_fn(|cx| {
+                let _tracked = track_task(&guard.state);
+                // Register before checking, so an `abort` in between is not missed.
+                guard.state.waker.register(cx.waker());
+                if guard.state.aborted.load(Ordering::SeqCst) {
+                    return Poll::Ready(Err(JoinError::Cancelled));
+                }
+                future
+                    .as_mut()
+                    .poll(cx)
+                    .map(|r| r.map_err(|_| JoinError::Panicked))
+            })
+            .await;
+            guard.finish(res);
+        };
+        (Self { rx, state }, task)
+    }
+}
+
+impl<T> JoinHandle<T> {
+    /// Cancels the task. If it is currently running, it will be dropped the
+    /// next time it would be polled. Awaiting the handle afterwards yields
+    /// [`JoinError::Cancelled`], unless the task already completed.
+    pub fn abort(&self) {
+        self.state.aborted.store(true, Ordering::SeqCst);
+        self.state.waker.wake();
+    }
+
+    /// Returns `true` if the task has finished, whether it completed,
+    /// panicked, or was cancelled.
+    pub fn is_finished(&self) -> bool {
+        self.state.is_finished()
+    }
+
+    pub(crate) fn task_state(&self) -> &Arc<TaskState> {
+        &self.state
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.rx).poll(cx) {
+            Poll::Ready(Ok(res)) => Poll::Ready(res),
+            // The task was dropped without completing.
+            Poll::Ready(Err(oneshot::Canceled)) => {
+                if let Some(err) = self.state.error() {
+                    Poll::Ready(Err(err))
+                } else if self.state.aborted.load(Ordering::SeqCst) {
+                    Poll::Ready(Err(JoinError::Cancelled))
+                } else {
+                    Poll::Ready(Err(JoinError::PoolShutdown))
+                }
+            }
+            Poll::Pending => {
+                self.state.join_waker.register(cx.waker());
+                match self.state.error() {
+                    Some(err) => Poll::Ready(Err(err)),
+                    None => Poll::Pending,
+                }
+            }
+        }
+    }
+}
@@ -2,15 +2,34 @@
 ///! API as [`futures-executor::ThreadPool`] targeting the web browser
 ///! environment. Instead of using spawning threads via `std::thread`, web
 ///! workers are created. This crate tries hard to make this process as
-///! seamless and painless as possible.
+///! seamless and painless as possible. On all other targets, the same API is
+///! backed by `std::thread`, which e.g. allows testing code using the pool
+///! natively.
 ///!
 ///! For further information and usage examples please check the [repository].
 ///!
 ///! [`futures_executor::ThreadPool`]: https://docs.rs/futures-executor/0.3.16/futures_executor/struct.ThreadPool.html
 ///! [repository]: https://github.com/wngr/wasm-futures-executor
+mod blocking;
+mod builder;
+mod env;
+mod error;
+mod global;
+mod join;
+#[cfg(not(target_arch = "wasm32"))]
+mod native;
 mod pool;
+mod runnable;
+mod timer;
+#[cfg(target_arch = "wasm32")]
+mod worker;
 
-pub use self::pool::ThreadPool;
+pub use self::builder::{Fallback, RestartPolicy, ThreadPoolBuilder};
+pub use self::env::{check_environment, is_pool_worker, Environment};
+pub use self::error::{GlobalError, JoinError, PoolError, SpawnError, WorkerError};
+pub use self::global::{init_global, spawn, spawn_ok, try_spawn, try_spawn_ok};
+pub use self::join::JoinHandle;
+pub use self::pool::{current_worker, Priority, ThreadPool, WorkerInfo};
 
-#[cfg(not(any(target_feature = "atomics", doc)))]
+#[cfg(all(target_arch = "wasm32", not(any(target_feature = "atomics", doc))))]
 compile_error!("Make sure to build std with `RUSTFLAGS='-C target-feature=+atomics,+bulk-memory,+mutable-globals'`");
@@ -0,0 +1,193 @@
+//! Backend for non-wasm targets: Workers are plain threads.
+
+use futures::executor::block_on;
+use futures::task::{waker, ArcWake};
+use log::*;
+use std::any::Any;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::task::Context;
+use std::thread::{self, Thread};
+use std::time::Instant;
+
+use crate::builder::RestartHistory;
+use crate::pool::{PoolState, Task, WorkerContext};
+use crate::{JoinError, PoolError, RestartPolicy, ThreadPoolBuilder, WorkerError};
+
+/// The settings needed to (re)start the workers of a pool.
+struct WorkerConfig {
+    name_prefix: String,
+    on_worker_error: Option<Arc<dyn Fn(WorkerError) + Send + Sync>>,
+    restart_policy: RestartPolicy,
+    restarts: Mutex<RestartHistory>,
+    /// The reference point for the times in `restarts`.
+    created: Instant,
+}
+
+impl WorkerConfig {
+    fn new(builder: &ThreadPoolBuilder) -> Arc<Self> {
+        Arc::new(Self {
+            name_prefix: builder.name_prefix.clone(),
+            on_worker_error: builder.on_worker_error.clone(),
+            restart_policy: builder.restart_policy,
+            restarts: Mutex::new(RestartHistory::default()),
+            created: Instant::now(),
+        })
+    }
+
+    fn name(&self, index: usize) -> String {
+        format!("{}{}", self.name_prefix, index)
+    }
+
+    /// Checks whether another restart is permitted by the pool's policy, and
+    /// records it if so.
+    fn may_restart(&self) -> bool {
+        let now = self.created.elapsed();
+        let mut restarts = self.restarts.lock().unwrap();
+        restarts.may_restart(self.restart_policy, now)
+    }
+}
+
+/// Starts all worker threads of the pool.
+pub(crate) async fn start_workers(
+    state: &Arc<PoolState>,
+    builder: &ThreadPoolBuilder,
+) -> Result<(), PoolError> {
+    let config = WorkerConfig::new(builder);
+    for index in 0..state.size() {
+        start(state, index, &config).map_err(|e| PoolError::WorkerInitFailed(e.to_string()))?;
+    }
+    Ok(())
+}
+
+/// There is no event loop to fall back to on native targets, so the tasks
+/// are run on a single worker thread instead.
+pub(crate) fn start_fallback(
+    state: &Arc<PoolState>,
+    builder: &ThreadPoolBuilder,
+) -> Result<(), PoolError> {
+    start(state, 0, &WorkerConfig::new(builder))
+        .map_err(|e| PoolError::WorkerInitFailed(e.to_string()))
+}
+
+/// Nothing to clean up, the worker threads exit on their own.
+pub(crate) fn forget(_state: &PoolState) {}
+
+/// Threads can't be killed. Instead, the workers drop their tasks and exit
+/// as soon as they notice that the pool was terminated.
+pub(crate) fn terminate(_state: &PoolState) {}
+
+/// Starts the threads running blocking closures and dedicated tasks.
+pub(crate) struct Launcher {
+    pub(crate) name_prefix: String,
+}
+
+impl Launcher {
+    pub(crate) fn new(builder: &ThreadPoolBuilder) -> Self {
+        Self {
+            name_prefix: builder.name_prefix.clone(),
+        }
+    }
+
+    /// Starts a thread which runs `task` to completion. `on_error` is called
+    /// with [`JoinError::WorkerDied`], if the thread couldn't be started.
+    pub(crate) fn spawn(
+        &self,
+        name: String,
+        task: Task,
+        on_error: Box<dyn FnOnce(JoinError) + Send>,
+    ) {
+        let n = name.clone();
+        if let Err(e) = thread::Builder::new()
+            .name(name)
+            .spawn(move || run_task(task))
+        {
+            error!("{}: {}", n, e);
+            on_error(JoinError::WorkerDied);
+        }
+    }
+
+    /// Threads can't be killed, so they run to completion.
+    pub(crate) fn terminate(&self) {}
+}
+
+fn start(state: &Arc<PoolState>, index: usize, config: &Arc<WorkerConfig>) -> io::Result<()> {
+    let ctx = WorkerContext {
+        state: state.clone(),
+        index,
+        name: config.name(index),
+    };
+    let config = config.clone();
+    thread::Builder::new()
+        .name(config.name(index))
+        .spawn(move || run(ctx, config))?;
+    Ok(())
+}
+
+fn run(ctx: WorkerContext, config: Arc<WorkerConfig>) {
+    let (state, index) = (ctx.state.clone(), ctx.index);
+    debug!("{}: Entry", ctx.name);
+    ctx.enter();
+    let driver = PoolState::drive(state.clone(), index);
+    let res = panic::catch_unwind(AssertUnwindSafe(|| block_on(driver)));
+    match res {
+        Ok(()) => {
+            ctx.leave();
+            info!("{}: Shutting down", ctx.name);
+        }
+        Err(panic) => {
+            let err = WorkerError {
+                index,
+                name: ctx.name.clone(),
+                message: panic_message(&*panic),
+                filename: String::new(),
+                lineno: 0,
+                colno: 0,
+            };
+            error!("{}", err);
+            state.worker_exited(index, JoinError::WorkerDied);
+            if !state.is_closed() && config.may_restart() {
+                state.worker_restarting(index);
+                match start(&state, index, &config) {
+                    Ok(()) => info!("{}: Restarted", ctx.name),
+                    Err(e) => {
+                        error!("{}: Restart failed: {:?}", ctx.name, e);
+                        state.worker_exited(index, JoinError::WorkerDied);
+                    }
+                }
+            }
+            if let Some(cb) = &config.on_worker_error {
+                cb(err);
+            }
+        }
+    }
+}
+
+/// Like `block_on`, but the task may call `block_on` itself, as the closures
+/// passed to `spawn_blocking` might.
+fn run_task(mut task: Task) {
+    struct Unparker(Thread);
+
+    impl ArcWake for Unparker {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.unpark();
+        }
+    }
+
+    let waker = waker(Arc::new(Unparker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    while task.as_mut().poll(&mut cx).is_pending() {
+        thread::park();
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "worker panicked".into()
+    }
+}
@@ -1,13 +1,26 @@
-use futures::channel::oneshot;
-use futures::future::BoxFuture;
-use futures::StreamExt;
-use futures::{channel::mpsc, Future};
-use js_sys::{JsString, Promise};
+use crossbeam_deque::{Injector, Steal, Stealer, Worker as Deque};
+use futures::future::{poll_fn, select, BoxFuture, Either, FutureObj};
+use futures::task::{self, AtomicWaker, Spawn};
+use futures::Future;
 use log::*;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use wasm_bindgen::{prelude::*, JsCast};
-use web_sys::{DedicatedWorkerGlobalScope, WorkerOptions, WorkerType};
+use std::cell::RefCell;
+use std::iter;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use wasm_bindgen::prelude::*;
+
+use crate::blocking::BlockingPool;
+use crate::env::{check_environment, hardware_concurrency, load_hardware_concurrency};
+use crate::join::TaskState;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::native as worker;
+use crate::runnable::Runnable;
+use crate::timer::sleep;
+#[cfg(target_arch = "wasm32")]
+use crate::worker;
+use crate::{Fallback, JoinError, JoinHandle, PoolError, SpawnError, ThreadPoolBuilder};
 
 trait AssertSendSync: Send + Sync {}
 impl AssertSendSync for ThreadPool {}
@@ -16,7 +29,8 @@ impl AssertSendSync for ThreadPool {}
 /// completion.
 ///
 /// The thread pool multiplexes any number of tasks onto a fixed number of
-/// worker threads.
+/// worker threads. On `wasm32`, these are web workers; on all other targets,
+/// they are regular threads, so code using the pool can be tested natively.
 ///
 /// This type is a clonable handle to the threadpool itself.
 /// Cloning it will only create a new reference, not a new threadpool.
@@ -41,174 +55,1014 @@ impl Clone for ThreadPool {
 impl Drop for ThreadPool {
     fn drop(&mut self) {
         if self.state.cnt.fetch_sub(1, Ordering::Relaxed) == 1 {
-            for _ in 0..self.state.size {
-                self.state.send(Message::Close);
-            }
+            self.state.close();
         }
     }
 }
 
-#[wasm_bindgen]
-pub struct LoaderHelper {}
-#[wasm_bindgen]
-impl LoaderHelper {
-    #[wasm_bindgen(js_name = mainJS)]
-    pub fn main_js(&self) -> JsString {
-        #[wasm_bindgen]
-        extern "C" {
-            #[wasm_bindgen(js_namespace = ["import", "meta"], js_name = url)]
-            static URL: JsString;
-        }
+/// Tasks spawned via [`Spawn`] are not subject to the queue capacity, as the
+/// trait can't report a full queue. Once the pool has been shut down,
+/// [`SpawnError::shutdown`](task::SpawnError::shutdown) is returned.
+///
+/// `&ThreadPool` implements [`Spawn`] as well, via the blanket impl of
+/// `futures-task`.
+impl Spawn for ThreadPool {
+    fn spawn_obj(&self, future: FutureObj<'static, ()>) -> Result<(), task::SpawnError> {
+        self.state
+            .push(Box::pin(future), Priority::Normal, false)
+            .map_err(|_| task::SpawnError::shutdown())
+    }
 
-        URL.clone()
+    fn status(&self) -> Result<(), task::SpawnError> {
+        if self.state.is_closed() {
+            Err(task::SpawnError::shutdown())
+        } else {
+            Ok(())
+        }
     }
 }
 
-#[wasm_bindgen(module = "/worker.js")]
-extern "C" {
-    #[wasm_bindgen(js_name = "startWorker")]
-    /// Returns Promise<Worker>
-    fn start_worker(
-        module: JsValue,
-        memory: JsValue,
-        shared_data: JsValue,
-        opts: WorkerOptions,
-        builder: LoaderHelper,
-    ) -> Promise;
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The worker of a pool this thread is running as, if any.
+    static CURRENT: RefCell<Option<WorkerContext>> = RefCell::new(None);
+    /// The local task queue of the worker this thread is running as.
+    static LOCAL: RefCell<Option<Deque<Arc<Runnable>>>> = RefCell::new(None);
 }
 
 impl ThreadPool {
     /// Creates a new [`ThreadPool`] with the provided count of web workers. The returned future
     /// will resolve after all workers have spawned and are ready to accept work.
-    pub async fn new(size: usize) -> Result<ThreadPool, JsValue> {
-        let (tx, rx) = mpsc::channel(64);
+    ///
+    /// See [`ThreadPoolBuilder`] for further configuration options.
+    pub async fn new(size: usize) -> Result<ThreadPool, PoolError> {
+        ThreadPoolBuilder::new().pool_size(size).create().await
+    }
+
+    /// Creates a new [`ThreadPool`] with `Navigator.hardwareConcurrency` web workers.
+    pub async fn max_threads() -> Result<Self, PoolError> {
+        ThreadPoolBuilder::new().create().await
+    }
+
+    /// Create a default thread pool configuration, which can then be customized.
+    ///
+    /// See the other methods on this type for details on the defaults.
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new()
+    }
+
+    /// Returns a handle to the pool the current thread is a worker of, for
+    /// example to spawn sub-tasks from within a task. Returns `None` if not
+    /// called on a worker of a pool.
+    pub fn current() -> Option<ThreadPool> {
+        CURRENT.with(|c| {
+            c.borrow().as_ref().map(|ctx| {
+                ctx.state.cnt.fetch_add(1, Ordering::Relaxed);
+                ThreadPool {
+                    state: ctx.state.clone(),
+                }
+            })
+        })
+    }
+
+    pub(crate) async fn create(builder: &ThreadPoolBuilder) -> Result<ThreadPool, PoolError> {
+        let fallback = match builder.fallback {
+            Fallback::Never => {
+                check_environment().check()?;
+                false
+            }
+            Fallback::Auto => match check_environment().check() {
+                Ok(()) => false,
+                Err(e) => {
+                    warn!("{}, running tasks on the current thread", e);
+                    true
+                }
+            },
+            Fallback::Always => true,
+        };
+        let size = match builder.pool_size {
+            _ if fallback => 1,
+            Some(size) => size,
+            None => {
+                load_hardware_concurrency().await;
+                hardware_concurrency()
+            }
+        };
+        let launcher = Arc::new(worker::Launcher::new(builder));
         let pool = ThreadPool {
             state: Arc::new(PoolState {
-                tx: parking_lot::Mutex::new(tx),
-                rx: tokio::sync::Mutex::new(rx),
+                id: NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed),
+                injectors: [Injector::new(), Injector::new(), Injector::new()],
+                queued: AtomicUsize::new(0),
+                active: AtomicUsize::new(0),
+                capacity: builder.queue_capacity,
+                closed: AtomicBool::new(false),
+                terminated: AtomicBool::new(false),
+                workers: (0..size).map(|_| WorkerSlot::new()).collect(),
+                space_waiters: Injector::new(),
+                exited: AtomicUsize::new(0),
+                exit_waiters: Injector::new(),
+                restarts: AtomicUsize::new(0),
+                fallback,
+                after_start: builder.after_start.clone(),
+                before_stop: builder.before_stop.clone(),
+                blocking: BlockingPool::new(builder, launcher.clone()),
+                launcher,
+                dedicated: AtomicUsize::new(0),
                 cnt: AtomicUsize::new(1),
-                size,
             }),
         };
 
-        for idx in 0..size {
-            let state = pool.state.clone();
-
-            let mut opts = WorkerOptions::new();
-            opts.type_(WorkerType::Module);
-            opts.name(&*format!("Worker-{}", idx));
-
-            // With a worker spun up send it the module/memory so it can start
-            // instantiating the wasm module. Later it might receive further
-            // messages about code to run on the wasm module.
-            let ptr = Arc::into_raw(state);
-            let _worker = wasm_bindgen_futures::JsFuture::from(start_worker(
-                wasm_bindgen::module(),
-                wasm_bindgen::memory(),
-                JsValue::from(ptr as u32),
-                opts,
-                LoaderHelper {},
-            ))
-            .await?;
-            // TODO: Check that workers actually spawned.
+        if fallback {
+            worker::start_fallback(&pool.state, builder)?;
+        } else {
+            worker::start_workers(&pool.state, builder).await?;
         }
         Ok(pool)
     }
 
-    /// Creates a new [`ThreadPool`] with `Navigator.hardwareConcurrency` web workers.
-    pub async fn max_threads() -> Result<Self, JsValue> {
-        #[wasm_bindgen]
-        extern "C" {
-            #[wasm_bindgen(js_namespace = navigator, js_name = hardwareConcurrency)]
-            static HARDWARE_CONCURRENCY: usize;
-        }
-        let pool_size = std::cmp::max(*HARDWARE_CONCURRENCY, 1);
-        Self::new(pool_size).await
-    }
-
     /// Spawns a task that polls the given future with output `()` to
     /// completion.
     ///
+    /// # Panics
+    ///
+    /// Panics if the task queue is full, or if the pool has been shut down.
+    /// Use [`ThreadPool::try_spawn_ok`] or [`ThreadPool::spawn_async`] if
+    /// that's a concern.
+    ///
     /// ```
+    /// # futures::executor::block_on(async {
     /// use wasm_futures_executor::ThreadPool;
     ///
-    /// let pool = ThreadPool::new().await.unwrap();
+    /// let pool = ThreadPool::new(2).await.unwrap();
     ///
     /// let future = async { /* ... */ };
     /// pool.spawn_ok(future);
+    /// # });
     /// ```
     pub fn spawn_ok<Fut>(&self, future: Fut)
     where
         Fut: Future<Output = ()> + Send + 'static,
     {
-        self.state.send(Message::Run(Box::pin(future)));
+        if let Err(e) = self.try_spawn_ok(future) {
+            panic!("Unable to spawn task: {}", e);
+        }
     }
 
-    /// Spawns a task. This function returns a future which eventually resolves to the output of
-    /// the computation.
+    /// Spawns a task that polls the given future with output `()` to
+    /// completion. Returns an error if the task queue is full or the pool
+    /// has been shut down.
+    ///
+    /// Submitting a task never takes a lock nor parks the calling thread, so
+    /// this is safe to call from the browser's main thread.
+    pub fn try_spawn_ok<Fut>(&self, future: Fut) -> Result<(), SpawnError>
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.state
+            .push(Box::pin(future), Priority::Normal, true)
+            .map_err(|(_, e)| e)
+    }
+
+    /// Spawns a task that polls the given future with output `()` to
+    /// completion. If the task queue is full, the returned future waits until
+    /// there is capacity again.
+    pub async fn spawn_async<Fut>(&self, future: Fut) -> Result<(), SpawnError>
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut task: Option<Task> = Some(Box::pin(future));
+        poll_fn(|cx| {
+            let t = task.take().expect("polled after completion");
+            let t = match self.state.push(t, Priority::Normal, true) {
+                Err((t, SpawnError::Full)) => t,
+                res => return Poll::Ready(res.map_err(|(_, e)| e)),
+            };
+            // Register interest in free capacity and retry, as a worker might have
+            // dequeued a task in the meantime.
+            self.state.space_waiters.push(cx.waker().clone());
+            match self.state.push(t, Priority::Normal, true) {
+                Err((t, SpawnError::Full)) => {
+                    task = Some(t);
+                    Poll::Pending
+                }
+                res => Poll::Ready(res.map_err(|(_, e)| e)),
+            }
+        })
+        .await
+    }
+
+    /// Spawns a task. This function returns a [`JoinHandle`] which eventually resolves to the
+    /// output of the computation.
     /// Note: The provided future is polled on the thread pool, no matter whether the returned
-    /// future is polled or not.
-    pub fn spawn<Fut>(
+    /// handle is polled or not.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the task queue is full, or if the pool has been shut down. Use
+    /// [`ThreadPool::try_spawn`] if that's a concern.
+    pub fn spawn<Fut>(&self, future: Fut) -> JoinHandle<Fut::Output>
+    where
+        Fut: Future + Send + 'static,
+        Fut::Output: Send + 'static,
+    {
+        let (handle, task) = JoinHandle::new(future);
+        self.spawn_ok(task);
+        handle
+    }
+
+    /// Spawns a task with the given priority. This function returns a [`JoinHandle`] which
+    /// eventually resolves to the output of the computation.
+    ///
+    /// Workers pick up tasks with a higher priority first. So that tasks with a lower priority
+    /// can't starve, every few tasks a worker looks at the lower priorities first. The priority
+    /// also applies whenever the task is woken up again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the task queue is full, or if the pool has been shut down. Use
+    /// [`ThreadPool::try_spawn_with_priority`] if that's a concern.
+    pub fn spawn_with_priority<Fut>(
         &self,
+        priority: Priority,
         future: Fut,
-    ) -> impl Future<Output = Result<Fut::Output, oneshot::Canceled>> + 'static
+    ) -> JoinHandle<Fut::Output>
     where
         Fut: Future + Send + 'static,
         Fut::Output: Send + 'static,
     {
-        let (tx, rx) = oneshot::channel();
-        let f = async move {
-            let res = future.await;
-            let _ = tx.send(res);
-        };
+        match self.try_spawn_with_priority(priority, future) {
+            Ok(handle) => handle,
+            Err(e) => panic!("Unable to spawn task: {}", e),
+        }
+    }
 
-        self.spawn_ok(f);
-        rx
+    /// Like [`ThreadPool::spawn_with_priority`], but returns an error if the task queue is full
+    /// or the pool has been shut down.
+    pub fn try_spawn_with_priority<Fut>(
+        &self,
+        priority: Priority,
+        future: Fut,
+    ) -> Result<JoinHandle<Fut::Output>, SpawnError>
+    where
+        Fut: Future + Send + 'static,
+        Fut::Output: Send + 'static,
+    {
+        let (handle, task) = JoinHandle::new(future);
+        self.state
+            .push(Box::pin(task), priority, true)
+            .map_err(|(_, e)| e)?;
+        Ok(handle)
+    }
+
+    /// Spawns a task. This function returns a [`JoinHandle`] which eventually resolves to the
+    /// output of the computation, or an error if the task queue is full or the pool has been shut
+    /// down.
+    pub fn try_spawn<Fut>(&self, future: Fut) -> Result<JoinHandle<Fut::Output>, SpawnError>
+    where
+        Fut: Future + Send + 'static,
+        Fut::Output: Send + 'static,
+    {
+        let (handle, task) = JoinHandle::new(future);
+        self.try_spawn_ok(task)?;
+        Ok(handle)
+    }
+
+    /// Runs the blocking closure `f` on a separate set of threads, so it
+    /// doesn't hold up the tasks on the workers of this pool. This function
+    /// returns a [`JoinHandle`] which eventually resolves to the return value
+    /// of `f`.
+    ///
+    /// Threads are started on demand, up to
+    /// [`ThreadPoolBuilder::max_blocking_threads`]; further closures wait
+    /// until one of them is free again. Idle threads exit after
+    /// [`ThreadPoolBuilder::blocking_keep_alive`]. Aborting the returned
+    /// handle has no effect once `f` has started. If the pool has been shut
+    /// down, the handle resolves to [`JoinError::PoolShutdown`].
+    ///
+    /// If the pool is a fallback pool, `f` runs like any other task instead.
+    pub fn spawn_blocking<F, R>(&self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (handle, task) = JoinHandle::new(async move { f() });
+        if self.state.fallback {
+            // On failure, the task is dropped and the handle resolves.
+            let _ = self.state.push(Box::pin(task), Priority::Normal, false);
+        } else if !self.state.is_closed() {
+            self.state
+                .blocking
+                .spawn(Box::pin(task), handle.task_state().clone());
+        }
+        handle
     }
-}
 
-enum Message {
-    Run(BoxFuture<'static, ()>),
-    Close,
+    /// Spawns a task onto a worker of its own, for tasks which run for a long
+    /// time, possibly for the lifetime of the page. The worker is started just
+    /// for this task and exits once it completes, so the capacity of the pool
+    /// isn't reduced. This function returns a [`JoinHandle`] which eventually
+    /// resolves to the output of the computation.
+    ///
+    /// The worker is not one of the pool's workers, so [`ThreadPool::current`]
+    /// and [`current_worker`] return `None` on it. If the worker crashes, the
+    /// handle resolves to [`JoinError::WorkerDied`]. If the pool has been shut
+    /// down, it resolves to [`JoinError::PoolShutdown`]. Shutting down the
+    /// pool doesn't affect a task which was already spawned, abort it via
+    /// [`JoinHandle::abort`] instead. Terminating the pool terminates its
+    /// worker as well.
+    ///
+    /// If the pool is a fallback pool, the task runs like any other task
+    /// instead.
+    pub fn spawn_dedicated<Fut>(&self, future: Fut) -> JoinHandle<Fut::Output>
+    where
+        Fut: Future + Send + 'static,
+        Fut::Output: Send + 'static,
+    {
+        let (handle, task) = JoinHandle::new(future);
+        if self.state.fallback {
+            // On failure, the task is dropped and the handle resolves.
+            let _ = self.state.push(Box::pin(task), Priority::Normal, false);
+        } else if !self.state.is_closed() {
+            let launcher = &self.state.launcher;
+            let id = self.state.dedicated.fetch_add(1, Ordering::Relaxed);
+            let name = format!("{}dedicated-{}", launcher.name_prefix, id);
+            let task_state = handle.task_state().clone();
+            let on_error = Box::new(move |err| task_state.fail(err));
+            launcher.spawn(name, Box::pin(task), on_error);
+        }
+        handle
+    }
+
+    /// Shuts down the pool gracefully. No new tasks are accepted anymore,
+    /// also not via other handles to this pool. The returned future resolves
+    /// after all queued and running tasks have completed and every worker
+    /// has exited.
+    ///
+    /// Note that tasks trying to spawn further tasks onto this pool during
+    /// shutdown will fail to do so.
+    ///
+    /// Tasks spawned via [`ThreadPool::spawn_dedicated`] and closures spawned
+    /// via [`ThreadPool::spawn_blocking`] which already started aren't waited
+    /// for, they keep running on their own threads until the pool is
+    /// terminated.
+    pub async fn shutdown(self) {
+        let state = self.state.clone();
+        state.close();
+        drop(self);
+        poll_fn(|cx| state.poll_exited(cx)).await;
+        worker::forget(&state);
+    }
+
+    /// Like [`ThreadPool::shutdown`], but if the workers didn't exit after
+    /// `timeout`, they are terminated, dropping all tasks still queued or
+    /// running. See [`ThreadPool::terminate`].
+    pub async fn shutdown_timeout(self, timeout: Duration) {
+        let state = self.state.clone();
+        let graceful = Box::pin(self.shutdown());
+        if let Either::Right(_) = select(graceful, Box::pin(sleep(timeout))).await {
+            warn!("Workers didn't shut down within {:?}, terminating", timeout);
+            state.terminate();
+        }
+    }
+
+    /// Returns an id identifying this pool, see [`WorkerInfo::pool_id`].
+    pub fn id(&self) -> usize {
+        self.state.id()
+    }
+
+    /// Returns the number of workers which are still alive, i.e. neither
+    /// crashed nor exited.
+    pub fn live_workers(&self) -> usize {
+        self.state.workers.len() - self.state.exited.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if this pool runs its tasks on the thread which created
+    /// it, instead of on web workers. See [`ThreadPoolBuilder::fallback`].
+    pub fn is_fallback(&self) -> bool {
+        self.state.fallback
+    }
+
+    /// Returns how often crashed workers have been restarted, see
+    /// [`ThreadPoolBuilder::restart_policy`].
+    pub fn restarts(&self) -> usize {
+        self.state.restarts.load(Ordering::SeqCst)
+    }
+
+    /// Terminates the pool's workers immediately via `Worker.terminate()`,
+    /// even if they are blocked by a long running task. All tasks still queued
+    /// or running are dropped; their [`JoinHandle`]s resolve to
+    /// [`JoinError::PoolShutdown`].
+    ///
+    /// The workers running closures passed to [`ThreadPool::spawn_blocking`]
+    /// and tasks spawned via [`ThreadPool::spawn_dedicated`] are terminated
+    /// as well, and their handles resolve to [`JoinError::PoolShutdown`].
+    /// On native targets, where threads can't be killed, those run to
+    /// completion instead.
+    ///
+    /// The pool's workers can only be terminated from the thread which
+    /// created the pool. If called from another thread, they are asked to
+    /// exit as soon as their current task yields.
+    pub fn terminate(&self) {
+        self.state.terminate();
+    }
 }
 
+pub(crate) type Task = BoxFuture<'static, ()>;
+
 pub struct PoolState {
-    tx: parking_lot::Mutex<mpsc::Sender<Message>>,
-    rx: tokio::sync::Mutex<mpsc::Receiver<Message>>,
+    id: usize,
+    /// Lock-free MPMC queues of tasks spawned from outside of the workers, one
+    /// per [`Priority`]. Tasks with [`Priority::Normal`] spawned by a worker go
+    /// to its local queue instead, see [`WorkerSlot::stealer`].
+    injectors: [Injector<Arc<Runnable>>; 3],
+    /// Number of tasks which haven't been picked up by a worker yet, usually
+    /// bounded by `capacity`.
+    queued: AtomicUsize,
+    /// Number of spawned tasks which haven't completed yet.
+    active: AtomicUsize,
+    capacity: usize,
+    closed: AtomicBool,
+    /// Set when workers should exit immediately, abandoning their tasks.
+    terminated: AtomicBool,
+    workers: Vec<WorkerSlot>,
+    /// Wakers of `spawn_async` calls waiting for free capacity.
+    space_waiters: Injector<Waker>,
+    /// Number of workers which have exited or were terminated.
+    exited: AtomicUsize,
+    /// Wakers of `shutdown` calls waiting for all workers to exit.
+    exit_waiters: Injector<Waker>,
+    /// Number of restarted workers.
+    restarts: AtomicUsize,
+    /// Tasks are run by a single driver on the thread which created the pool.
+    fallback: bool,
+    after_start: Option<Arc<dyn Fn(WorkerInfo) + Send + Sync>>,
+    before_stop: Option<Arc<dyn Fn(WorkerInfo) + Send + Sync>>,
+    /// Runs the closures passed to [`ThreadPool::spawn_blocking`].
+    blocking: Arc<BlockingPool>,
+    /// Starts the workers of [`ThreadPool::spawn_dedicated`] and of
+    /// `blocking`.
+    launcher: Arc<worker::Launcher>,
+    /// Number of workers started by [`ThreadPool::spawn_dedicated`].
+    dedicated: AtomicUsize,
     cnt: AtomicUsize,
-    size: usize,
 }
 
 impl PoolState {
-    fn send(&self, msg: Message) {
-        self.tx.lock().start_send(msg).unwrap()
+    pub(crate) fn id(&self) -> usize {
+        self.id
     }
 
-    fn work(slf: Arc<PoolState>) {
-        let driver = async move {
-            let global = js_sys::global().unchecked_into::<DedicatedWorkerGlobalScope>();
-            while let Some(msg) = slf.rx.lock().await.next().await {
-                match msg {
-                    Message::Run(future) => wasm_bindgen_futures::spawn_local(future),
-                    Message::Close => break,
-                }
+    pub(crate) fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Enqueues a task without taking any locks. On failure, the task is handed back.
+    /// The queue capacity is only enforced if `bounded` is set.
+    fn push(
+        self: &Arc<Self>,
+        task: Task,
+        priority: Priority,
+        bounded: bool,
+    ) -> Result<(), (Task, SpawnError)> {
+        // Reserve a slot first, so that a worker observing `closed` and an
+        // empty queue can be sure that no task is about to be enqueued.
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.capacity && bounded {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err((task, SpawnError::Full));
+        }
+        if self.closed.load(Ordering::SeqCst) {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            self.wake_workers();
+            return Err((task, SpawnError::Shutdown));
+        }
+        self.active.fetch_add(1, Ordering::SeqCst);
+        self.enqueue(Runnable::new(task, priority, self));
+        Ok(())
+    }
+
+    /// Queues a task which was spawned or woken up. Tasks with
+    /// [`Priority::Normal`] stay on the current worker, if it belongs to this
+    /// pool.
+    pub(crate) fn enqueue(&self, task: Arc<Runnable>) {
+        if self.terminated.load(Ordering::SeqCst) {
+            return;
+        }
+        let res = match task.priority() {
+            Priority::Normal => self.push_local(task),
+            _ => Err(task),
+        };
+        if let Err(task) = res {
+            self.injector(task.priority()).push(task);
+        }
+        // Pairs with the fence in `poll_task`: Either the task is visible to
+        // a worker going idle, or that worker is visible as idle here.
+        atomic::fence(Ordering::SeqCst);
+        self.wake_one();
+    }
+
+    fn injector(&self, priority: Priority) -> &Injector<Arc<Runnable>> {
+        &self.injectors[priority as usize]
+    }
+
+    /// Records that a task completed or was dropped.
+    pub(crate) fn task_done(&self) {
+        if self.active.fetch_sub(1, Ordering::SeqCst) == 1 && self.is_closed() {
+            self.wake_workers();
+        }
+    }
+
+    /// Pushes the task onto the local queue, if called on one of this pool's
+    /// workers. Otherwise, the task is handed back.
+    fn push_local(&self, task: Arc<Runnable>) -> Result<(), Arc<Runnable>> {
+        if !CURRENT.with(|c| matches!(&*c.borrow(), Some(ctx) if ctx.state.id == self.id)) {
+            return Err(task);
+        }
+        LOCAL.with(|l| match &*l.borrow() {
+            Some(local) => {
+                local.push(task);
+                Ok(())
+            }
+            None => Err(task),
+        })
+    }
+
+    /// Resolves to the next task for the worker `idx`, or to `None` once the
+    /// worker should exit.
+    fn poll_task(&self, idx: usize, cx: &mut Context<'_>) -> Poll<Option<Arc<Runnable>>> {
+        let slot = &self.workers[idx];
+        // Register before checking the queue to not miss any wake-ups.
+        slot.waker.register(cx.waker());
+        if self.terminated.load(Ordering::SeqCst) {
+            slot.idle.store(false, Ordering::SeqCst);
+            return Poll::Ready(None);
+        }
+        let task = self.find_task(idx).or_else(|| {
+            // Announce that this worker is idle before checking once more, so
+            // a task queued in between either is found or wakes this worker.
+            slot.idle.store(true, Ordering::SeqCst);
+            atomic::fence(Ordering::SeqCst);
+            self.find_task(idx)
+        });
+        if let Some(task) = task {
+            slot.idle.store(false, Ordering::SeqCst);
+            if task.take_fresh() {
+                self.queued.fetch_sub(1, Ordering::SeqCst);
+                wake_all(&self.space_waiters);
             }
-            info!("{}: Shutting down", global.name());
-            global.close();
+            return Poll::Ready(Some(task));
+        }
+        // Tasks which are waiting to be woken up might continue on any worker.
+        if self.closed.load(Ordering::SeqCst)
+            && self.queued.load(Ordering::SeqCst) == 0
+            && self.active.load(Ordering::SeqCst) == 0
+        {
+            slot.idle.store(false, Ordering::SeqCst);
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Takes a task with the highest priority available. Every so often, lower
+    /// priorities are looked at first, so they can't starve.
+    fn find_task(&self, idx: usize) -> Option<Arc<Runnable>> {
+        let tick = self.workers[idx].ticks.fetch_add(1, Ordering::Relaxed);
+        let first = match tick % STARVATION_INTERVAL {
+            0 => Priority::Low,
+            t if t == STARVATION_INTERVAL / 2 => Priority::Normal,
+            _ => Priority::High,
         };
-        wasm_bindgen_futures::spawn_local(driver);
+        iter::once(first)
+            .chain(Priority::ALL)
+            .find_map(|priority| match priority {
+                Priority::Normal => self.find_normal_task(idx),
+                _ => iter::repeat_with(|| self.injector(priority).steal())
+                    .find(|s| !s.is_retry())
+                    .and_then(|s| s.success()),
+            })
+    }
+
+    /// Takes a task from the local queue of the current thread first, then
+    /// from the global queue, and finally tries to steal one from the other
+    /// workers, starting after `idx`.
+    fn find_normal_task(&self, idx: usize) -> Option<Arc<Runnable>> {
+        let injector = self.injector(Priority::Normal);
+        LOCAL.with(|l| {
+            let local = l.borrow();
+            let local = local.as_ref();
+            if let Some(task) = local.and_then(Deque::pop) {
+                return Some(task);
+            }
+            let steal = |other: &Stealer<Arc<Runnable>>| match local {
+                Some(local) => other.steal_batch_and_pop(local),
+                None => other.steal(),
+            };
+            let steal_global = || match local {
+                Some(local) => injector.steal_batch_and_pop(local),
+                None => injector.steal(),
+            };
+            let n = self.workers.len();
+            iter::repeat_with(|| {
+                steal_global().or_else(|| {
+                    (1..n)
+                        .map(|i| &self.workers[(idx + i) % n])
+                        .map(|slot| match &*slot.stealer.lock().unwrap() {
+                            Some(other) => steal(other),
+                            None => Steal::Empty,
+                        })
+                        .collect()
+                })
+            })
+            .find(|s| !s.is_retry())
+            .and_then(|s| s.success())
+        })
+    }
+
+    /// Moves the tasks in the local queue of the worker `idx` to the global
+    /// queue, or drops them if the pool was terminated. Not blocking, as this
+    /// might run on the main thread.
+    fn drain_local(&self, idx: usize) {
+        let stealer = match self.workers[idx].stealer.try_lock() {
+            Ok(mut stealer) => stealer.take(),
+            Err(_) => None,
+        };
+        if let Some(stealer) = stealer {
+            self.requeue(&stealer);
+        }
+    }
+
+    fn requeue(&self, stealer: &Stealer<Arc<Runnable>>) {
+        loop {
+            match stealer.steal() {
+                Steal::Success(_) if self.terminated.load(Ordering::SeqCst) => {}
+                Steal::Success(task) => self.injector(task.priority()).push(task),
+                Steal::Retry => continue,
+                Steal::Empty => break,
+            }
+        }
+        self.wake_workers();
+    }
+
+    /// Wakes a single idle worker, if there is any. Busy workers look for
+    /// more tasks anyway once they are done.
+    fn wake_one(&self) {
+        // Clearing the flag claims the worker, so concurrent pushes wake
+        // different ones.
+        if let Some(slot) = self
+            .workers
+            .iter()
+            .find(|slot| slot.idle.swap(false, Ordering::SeqCst))
+        {
+            slot.waker.wake();
+        }
+    }
+
+    /// Wakes all workers, for example so they notice that the pool was
+    /// closed.
+    fn wake_workers(&self) {
+        for slot in &self.workers {
+            slot.waker.wake();
+        }
+    }
+
+    /// Closes the task queue. Workers will finish all tasks already queued
+    /// and shut down afterwards.
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.wake_workers();
+        wake_all(&self.space_waiters);
+    }
+
+    /// Closes the task queue and terminates all workers, dropping their
+    /// tasks. Workers are hard terminated if they were created on this
+    /// thread, otherwise they exit as soon as possible.
+    fn terminate(&self) {
+        self.terminated.store(true, Ordering::SeqCst);
+        self.close();
+        // Drop all queued tasks, so their handles resolve.
+        for injector in &self.injectors {
+            while !matches!(injector.steal(), Steal::Empty) {}
+        }
+        self.blocking.clear(JoinError::PoolShutdown);
+        for idx in 0..self.workers.len() {
+            self.drain_local(idx);
+        }
+        self.launcher.terminate();
+        worker::terminate(self);
+    }
+
+    /// Records that the worker `idx` has exited. The task it was polling, if
+    /// any, is failed with `err`. Returns `false` if the exit was already
+    /// recorded.
+    pub(crate) fn worker_exited(&self, idx: usize, err: JoinError) -> bool {
+        let slot = &self.workers[idx];
+        if slot.exited.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        slot.busy.store(false, Ordering::SeqCst);
+        slot.idle.store(false, Ordering::SeqCst);
+        // The worker is gone, so this can't be contended for long. Don't
+        // block though, as this might run on the main thread.
+        if let Ok(mut tasks) = slot.tasks.try_lock() {
+            for task in tasks.drain(..).filter_map(|t| t.upgrade()) {
+                task.fail(err);
+            }
+        }
+        if let Some(task) = slot.running.try_lock().ok().and_then(|mut r| r.take()) {
+            task.abandon();
+            self.task_done();
+        }
+        // Queued tasks haven't started yet, so other workers can take over.
+        self.drain_local(idx);
+        self.exited.fetch_add(1, Ordering::SeqCst);
+        wake_all(&self.exit_waiters);
+        true
+    }
+
+    /// Returns how many polls the worker `idx` has started, if it is alive
+    /// and polling a task right now. If this doesn't change for a while, the
+    /// worker is stuck.
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn worker_progress(&self, idx: usize) -> Option<usize> {
+        let slot = &self.workers[idx];
+        if slot.exited.load(Ordering::SeqCst) || !slot.busy.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(slot.polls.load(Ordering::SeqCst))
+    }
+
+    /// Records that the worker `idx` is about to be replaced.
+    pub(crate) fn worker_restarting(&self, idx: usize) {
+        self.workers[idx].started.store(false, Ordering::SeqCst);
+        if self.workers[idx].exited.swap(false, Ordering::SeqCst) {
+            self.exited.fetch_sub(1, Ordering::SeqCst);
+        }
+        self.restarts.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Whether the worker `idx` has called `worker_entry_point`.
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn has_started(&self, idx: usize) -> bool {
+        self.workers[idx].started.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn all_exited(&self) -> bool {
+        self.exited.load(Ordering::SeqCst) == self.workers.len()
     }
+
+    fn poll_exited(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.all_exited() {
+            return Poll::Ready(());
+        }
+        self.exit_waiters.push(cx.waker().clone());
+        if self.all_exited() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Drives the worker `idx`: Tasks are taken from the queue and polled on
+    /// the current thread. A task which isn't ready yet is queued again once
+    /// woken up, so it might continue on another worker. Resolves after the
+    /// pool was closed and all tasks have completed. The caller reports the
+    /// exit via [`PoolState::worker_exited`], after running the `before_stop`
+    /// hook.
+    pub(crate) async fn drive(slf: Arc<PoolState>, idx: usize) {
+        let slot = &slf.workers[idx];
+        while let Some(task) = poll_fn(|cx| slf.poll_task(idx, cx)).await {
+            *slot.running.lock().unwrap() = Some(task.clone());
+            slot.polls.fetch_add(1, Ordering::SeqCst);
+            slot.busy.store(true, Ordering::SeqCst);
+            if task.run() {
+                slf.task_done();
+            }
+            slot.busy.store(false, Ordering::SeqCst);
+            slot.running.lock().unwrap().take();
+            yield_now().await;
+        }
+    }
+}
+
+/// Lets other futures on the current thread make progress, for example ones
+/// resolving JS promises on wasm.
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+fn wake_all(waiters: &Injector<Waker>) {
+    loop {
+        match waiters.steal() {
+            Steal::Success(waker) => waker.wake(),
+            Steal::Retry => continue,
+            Steal::Empty => break,
+        }
+    }
+}
+
+/// The priority of a task spawned via [`ThreadPool::spawn_with_priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// For latency sensitive tasks.
+    High,
+    /// The priority of tasks spawned via any other method.
+    Normal,
+    /// For background work.
+    Low,
+}
+
+impl Priority {
+    const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+}
+
+/// A worker looks at the lower priorities first once every this many times it
+/// looks for a task.
+const STARVATION_INTERVAL: usize = 16;
+
+/// Per worker bookkeeping of the pool.
+struct WorkerSlot {
+    /// Waker of the worker's driver.
+    waker: AtomicWaker,
+    started: AtomicBool,
+    exited: AtomicBool,
+    /// Tasks with a [`JoinHandle`], which are being polled by the worker.
+    tasks: Mutex<Vec<Weak<TaskState>>>,
+    /// The task being polled by the worker.
+    running: Mutex<Option<Arc<Runnable>>>,
+    /// Handle to the local queue of the worker, which is owned by the worker
+    /// thread, see `LOCAL`. Idle workers steal from it.
+    stealer: Mutex<Option<Stealer<Arc<Runnable>>>>,
+    /// Number of times the worker looked for a task, see
+    /// [`PoolState::find_task`].
+    ticks: AtomicUsize,
+    /// Number of times the worker started polling a task.
+    polls: AtomicUsize,
+    /// Whether the worker is polling a task right now.
+    busy: AtomicBool,
+    /// Whether the worker is waiting for a task, and hasn't been woken up
+    /// yet, see [`PoolState::wake_one`].
+    idle: AtomicBool,
+}
+
+impl WorkerSlot {
+    fn new() -> Self {
+        Self {
+            waker: AtomicWaker::new(),
+            started: AtomicBool::new(false),
+            exited: AtomicBool::new(false),
+            tasks: Mutex::new(Vec::new()),
+            running: Mutex::new(None),
+            stealer: Mutex::new(None),
+            ticks: AtomicUsize::new(0),
+            polls: AtomicUsize::new(0),
+            busy: AtomicBool::new(false),
+            idle: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Registers a task with the worker which is about to poll it, so it can be
+/// failed if the worker dies meanwhile. The registration ends when the
+/// returned guard is dropped. This is a no-op outside of pool workers.
+pub(crate) fn track_task(task: &Arc<TaskState>) -> Option<TrackGuard> {
+    CURRENT.with(|c| {
+        let (state, index) = c.borrow().as_ref().map(|c| (c.state.clone(), c.index))?;
+        let task = Arc::downgrade(task);
+        state.workers[index]
+            .tasks
+            .lock()
+            .unwrap()
+            .push(task.clone());
+        Some(TrackGuard { state, index, task })
+    })
 }
 
-/// Entry point invoked by the web worker. The passed pointer will be unconditionally interpreted
-/// as an `Arc<PoolState>`.
-#[wasm_bindgen(skip_typescript)]
-pub fn worker_entry_point(state_ptr: u32) {
-    let state = unsafe { Arc::<PoolState>::from_raw(state_ptr as *const PoolState) };
+pub(crate) struct TrackGuard {
+    state: Arc<PoolState>,
+    index: usize,
+    task: Weak<TaskState>,
+}
+
+impl Drop for TrackGuard {
+    fn drop(&mut self) {
+        // If polling the task panicked, the worker is about to die. Leave the
+        // task registered, so `worker_exited` fails it.
+        if std::thread::panicking() {
+            return;
+        }
+        let mut tasks = self.state.workers[self.index].tasks.lock().unwrap();
+        tasks.retain(|t| !t.ptr_eq(&self.task));
+    }
+}
 
-    let name = js_sys::global()
-        .unchecked_into::<DedicatedWorkerGlobalScope>()
-        .name();
-    debug!("{}: Entry", name);
-    PoolState::work(state);
+/// Describes a worker of a [`ThreadPool`], see [`current_worker`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerInfo {
+    /// Index of the worker within its pool.
+    pub index: usize,
+    /// Name of the worker, see [`ThreadPoolBuilder::name_prefix`].
+    pub name: String,
+    /// Id of the pool, see [`ThreadPool::id`].
+    pub pool_id: usize,
+}
+
+/// Returns information about the pool worker the current thread is, for
+/// example for logging or to shard data per worker. Returns `None` if not
+/// called on a worker of a pool.
+pub fn current_worker() -> Option<WorkerInfo> {
+    CURRENT.with(|c| c.borrow().as_ref().map(WorkerContext::info))
+}
+
+/// Data handed over to a newly spawned worker.
+#[derive(Clone)]
+pub(crate) struct WorkerContext {
+    pub(crate) state: Arc<PoolState>,
+    pub(crate) index: usize,
+    pub(crate) name: String,
+}
+
+impl WorkerContext {
+    /// Marks the current thread as the worker described by this context,
+    /// and runs the `after_start` hook.
+    pub(crate) fn enter(&self) {
+        CURRENT.with(|c| *c.borrow_mut() = Some(self.clone()));
+        let local = Deque::new_fifo();
+        let stale = self.state.workers[self.index]
+            .stealer
+            .lock()
+            .unwrap()
+            .replace(local.stealer());
+        LOCAL.with(|l| *l.borrow_mut() = Some(local));
+        // Left behind by a crashed predecessor.
+        if let Some(stale) = stale {
+            self.state.requeue(&stale);
+        }
+        self.after_start();
+        self.state.workers[self.index]
+            .started
+            .store(true, Ordering::SeqCst);
+    }
+
+    /// Marks the current thread as the worker described by this context
+    /// while running `f`, for a driver sharing the thread with other code.
+    /// Unlike [`WorkerContext::enter`], this doesn't run any hooks.
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let current = CURRENT.with(|c| c.replace(Some(self.clone())));
+        let local = LOCAL.with(|l| l.replace(None));
+        let res = f();
+        CURRENT.with(|c| *c.borrow_mut() = current);
+        LOCAL.with(|l| *l.borrow_mut() = local);
+        res
+    }
+
+    pub(crate) fn after_start(&self) {
+        if let Some(f) = &self.state.after_start {
+            f(self.info());
+        }
+    }
+
+    pub(crate) fn before_stop(&self) {
+        // Terminated workers drop their tasks instead of finishing them.
+        if self.state.terminated.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Some(f) = &self.state.before_stop {
+            f(self.info());
+        }
+    }
+
+    pub(crate) fn info(&self) -> WorkerInfo {
+        WorkerInfo {
+            index: self.index,
+            name: self.name.clone(),
+            pool_id: self.state.id(),
+        }
+    }
+
+    /// Runs the `before_stop` hook, records the exit of the worker, and
+    /// reverts [`WorkerContext::enter`].
+    pub(crate) fn leave(&self) {
+        self.before_stop();
+        self.state
+            .worker_exited(self.index, JoinError::PoolShutdown);
+        CURRENT.with(|c| c.borrow_mut().take());
+        LOCAL.with(|l| l.borrow_mut().take());
+    }
 }
@@ -0,0 +1,132 @@
+use futures::task::{waker_ref, ArcWake};
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::Context;
+
+use crate::pool::{PoolState, Priority, Task};
+
+const IDLE: u8 = 0;
+const SCHEDULED: u8 = 1;
+const RUNNING: u8 = 2;
+/// Woken while running, so it must be polled again.
+const NOTIFIED: u8 = 3;
+const DONE: u8 = 4;
+
+/// A spawned task. Whenever it is woken, it is put back into the queue of its
+/// pool, so it may continue on any worker, just like with
+/// `futures_executor::ThreadPool`.
+pub(crate) struct Runnable {
+    future: Mutex<Option<Task>>,
+    state: AtomicU8,
+    /// Whether the task hasn't been picked up by a worker yet.
+    fresh: AtomicBool,
+    /// Set if the worker polling the task died, which leaves the future in an
+    /// unknown state.
+    abandoned: AtomicBool,
+    priority: Priority,
+    pool: Weak<PoolState>,
+}
+
+impl Runnable {
+    /// Wraps `future` into a task, which is considered to be queued already.
+    pub(crate) fn new(future: Task, priority: Priority, pool: &Arc<PoolState>) -> Arc<Self> {
+        Arc::new(Self {
+            future: Mutex::new(Some(future)),
+            state: AtomicU8::new(SCHEDULED),
+            fresh: AtomicBool::new(true),
+            abandoned: AtomicBool::new(false),
+            priority,
+            pool: Arc::downgrade(pool),
+        })
+    }
+
+    pub(crate) fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Returns `true` when called for the first time, i.e. when the task is
+    /// picked up by a worker for the first time.
+    pub(crate) fn take_fresh(&self) -> bool {
+        self.fresh.swap(false, Ordering::SeqCst)
+    }
+
+    /// Polls the task once. Must only be called with a task taken from the
+    /// queue. Returns whether the task has completed.
+    pub(crate) fn run(self: &Arc<Self>) -> bool {
+        self.state.store(RUNNING, Ordering::SeqCst);
+        let waker = waker_ref(self);
+        let mut cx = Context::from_waker(&waker);
+        let mut future = self.future.lock().unwrap();
+        let done = match future.as_mut() {
+            Some(f) => f.as_mut().poll(&mut cx).is_ready(),
+            None => true,
+        };
+        if done {
+            *future = None;
+            self.state.store(DONE, Ordering::SeqCst);
+            return true;
+        }
+        drop(future);
+        if self
+            .state
+            .compare_exchange(RUNNING, IDLE, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            // Woken while being polled.
+            self.state.store(SCHEDULED, Ordering::SeqCst);
+            self.schedule();
+        }
+        false
+    }
+
+    /// Gives up on the task, because the worker polling it died. The future
+    /// is leaked, as it can't be dropped safely.
+    pub(crate) fn abandon(&self) {
+        self.abandoned.store(true, Ordering::SeqCst);
+    }
+
+    fn schedule(self: &Arc<Self>) {
+        if let Some(pool) = self.pool.upgrade() {
+            pool.enqueue(self.clone());
+        }
+    }
+}
+
+impl ArcWake for Runnable {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        let mut state = arc_self.state.load(Ordering::SeqCst);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match arc_self
+                .state
+                .compare_exchange(state, next, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+        if state == IDLE {
+            arc_self.schedule();
+        }
+    }
+}
+
+impl Drop for Runnable {
+    fn drop(&mut self) {
+        if self.abandoned.load(Ordering::SeqCst) {
+            let future = self.future.get_mut().unwrap_or_else(|e| e.into_inner());
+            mem::forget(future.take());
+        } else if self.state.load(Ordering::SeqCst) != DONE {
+            // Dropped before completion, for example because no one holds on
+            // to its waker anymore.
+            if let Some(pool) = self.pool.upgrade() {
+                pool.task_done();
+            }
+        }
+    }
+}
@@ -0,0 +1,714 @@
+use futures::channel::{mpsc, oneshot};
+use futures::future::{poll_fn, select, select_all, try_join_all, Either};
+use futures::{Future, FutureExt, StreamExt};
+use js_sys::{JsString, Promise};
+use log::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{RequestCredentials, WorkerOptions, WorkerType};
+
+use crate::builder::RestartHistory;
+use crate::env::cross_origin_isolated;
+use crate::pool::{PoolState, Task, WorkerContext};
+use crate::timer::sleep;
+use crate::{JoinError, PoolError, RestartPolicy, ThreadPoolBuilder, WorkerError};
+
+thread_local! {
+    /// Handles to the web workers of all pools created on this thread, keyed
+    /// by pool id. Workers can only be used on the thread which created them,
+    /// so they can't be part of the `PoolState`.
+    static WORKERS: RefCell<HashMap<usize, PoolWorkers>> = RefCell::new(HashMap::new());
+}
+
+#[wasm_bindgen]
+pub struct LoaderHelper {}
+#[wasm_bindgen]
+impl LoaderHelper {
+    #[wasm_bindgen(js_name = mainJS)]
+    pub fn main_js(&self) -> JsString {
+        #[wasm_bindgen]
+        extern "C" {
+            #[wasm_bindgen(js_namespace = ["import", "meta"], js_name = url)]
+            static URL: JsString;
+        }
+
+        URL.clone()
+    }
+}
+
+#[wasm_bindgen]
+extern "C" {
+    /// A web `Worker`, or a Node.js `worker_threads.Worker` wrapped by
+    /// `worker.js` to look like one.
+    #[derive(Clone)]
+    type PoolWorker;
+
+    #[wasm_bindgen(method, structural)]
+    fn terminate(this: &PoolWorker);
+
+    #[wasm_bindgen(method, structural, setter)]
+    fn set_onerror(this: &PoolWorker, handler: Option<&js_sys::Function>);
+
+    /// What `startWorker` resolves to.
+    type StartedWorker;
+
+    #[wasm_bindgen(method, structural, getter)]
+    fn worker(this: &StartedWorker) -> PoolWorker;
+
+    /// Resolves once the worker is ready, see `workerReady`.
+    #[wasm_bindgen(method, structural, getter)]
+    fn ready(this: &StartedWorker) -> Promise;
+}
+
+#[wasm_bindgen(module = "/worker.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = "startWorker")]
+    /// Returns Promise<StartedWorker>
+    fn start_worker(
+        module: JsValue,
+        memory: JsValue,
+        shared_data: JsValue,
+        opts: WorkerOptions,
+        builder: LoaderHelper,
+    ) -> Promise;
+
+    #[wasm_bindgen(js_name = "workerUrl")]
+    fn worker_url() -> String;
+
+    #[wasm_bindgen(js_name = "closeWorker")]
+    fn close_worker();
+}
+
+/// The settings needed to (re)start the workers of a pool.
+struct WorkerConfig {
+    name_prefix: String,
+    credentials: Option<RequestCredentials>,
+    on_worker_error: Option<Arc<dyn Fn(WorkerError) + Send + Sync>>,
+    restart_policy: RestartPolicy,
+    startup_timeout: Duration,
+    unresponsive_timeout: Option<Duration>,
+}
+
+impl WorkerConfig {
+    fn new(builder: &ThreadPoolBuilder) -> Rc<Self> {
+        Rc::new(Self {
+            name_prefix: builder.name_prefix.clone(),
+            credentials: builder.credentials,
+            on_worker_error: builder.on_worker_error.clone(),
+            restart_policy: builder.restart_policy,
+            startup_timeout: builder.startup_timeout,
+            unresponsive_timeout: builder.unresponsive_timeout,
+        })
+    }
+
+    fn name(&self, index: usize) -> String {
+        format!("{}{}", self.name_prefix, index)
+    }
+}
+
+/// The web workers of a pool, see `WORKERS`.
+struct PoolWorkers {
+    state: Weak<PoolState>,
+    config: Rc<WorkerConfig>,
+    workers: Vec<WorkerHandle>,
+    restarts: RestartHistory,
+}
+
+impl PoolWorkers {
+    /// Checks whether another restart is permitted by the pool's policy, and
+    /// records it if so.
+    fn may_restart(&mut self) -> bool {
+        let now = Duration::from_secs_f64(js_sys::Date::now() / 1000.);
+        self.restarts.may_restart(self.config.restart_policy, now)
+    }
+}
+
+struct WorkerHandle {
+    worker: PoolWorker,
+    _on_error: Closure<dyn FnMut(JsValue)>,
+}
+
+impl WorkerHandle {
+    /// Takes ownership of a started `worker` and watches it for crashes.
+    fn new(
+        worker: PoolWorker,
+        state: &Arc<PoolState>,
+        index: usize,
+        config: &WorkerConfig,
+    ) -> Self {
+        let state = Arc::downgrade(state);
+        let name = config.name(index);
+        let callback = config.on_worker_error.clone();
+        let w = worker.clone();
+        let on_error = Closure::wrap(Box::new(move |ev: JsValue| {
+            // An `ErrorEvent`, or a lookalike on Node.js.
+            let number = |key: &str| get(&ev, key).and_then(|v| v.as_f64()).unwrap_or(0.) as u32;
+            let err = WorkerError {
+                index,
+                name: name.clone(),
+                message: error_message(&ev),
+                filename: get(&ev, "filename")
+                    .and_then(|v| v.as_string())
+                    .unwrap_or_default(),
+                lineno: number("lineno"),
+                colno: number("colno"),
+            };
+            worker_died(&state, &w, err, callback.as_ref());
+        }) as Box<dyn FnMut(JsValue)>);
+        worker.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+        Self {
+            worker,
+            _on_error: on_error,
+        }
+    }
+}
+
+/// Terminates the crashed or stuck `worker`, fails its tasks and replaces it
+/// if the pool's policy permits.
+fn worker_died(
+    state: &Weak<PoolState>,
+    worker: &PoolWorker,
+    err: WorkerError,
+    callback: Option<&Arc<dyn Fn(WorkerError) + Send + Sync>>,
+) {
+    // Ignore further error events of the dead worker.
+    worker.set_onerror(None);
+    // The wasm instance of the worker can't be trusted anymore.
+    worker.terminate();
+    let state = state.upgrade();
+    if let Some(state) = &state {
+        // The watchdog and a crash may both report the same worker.
+        if !state.worker_exited(err.index, JoinError::WorkerDied) {
+            return;
+        }
+    }
+    error!("{}", err);
+    if let Some(state) = state {
+        if !state.is_closed() && may_restart(state.id()) {
+            wasm_bindgen_futures::spawn_local(restart(state, err.index));
+        }
+    }
+    if let Some(cb) = callback {
+        cb(err);
+    }
+}
+
+/// Starts all workers of the pool concurrently. The returned future resolves
+/// once all of them are ready to accept work.
+pub(crate) async fn start_workers(
+    state: &Arc<PoolState>,
+    builder: &ThreadPoolBuilder,
+) -> Result<(), PoolError> {
+    let config = WorkerConfig::new(builder);
+    let workers = try_join_all((0..state.size()).map(|idx| start(state, idx, &config))).await?;
+    if let Some(timeout) = config.unresponsive_timeout {
+        wasm_bindgen_futures::spawn_local(watchdog(Arc::downgrade(state), timeout));
+    }
+    register(state, config, workers);
+    Ok(())
+}
+
+/// Checks every `timeout` for workers which are still in the same poll of a
+/// task as at the previous check, and handles them like crashed workers.
+async fn watchdog(state: Weak<PoolState>, timeout: Duration) {
+    let mut last = Vec::new();
+    loop {
+        sleep(timeout).await;
+        let state = match state.upgrade() {
+            Some(state) if !state.all_exited() => state,
+            _ => return,
+        };
+        let progress: Vec<_> = (0..state.size())
+            .map(|idx| state.worker_progress(idx))
+            .collect();
+        for (index, p) in progress.iter().enumerate() {
+            if p.is_some() && last.get(index) == Some(p) {
+                worker_unresponsive(&state, index);
+            }
+        }
+        last = progress;
+    }
+}
+
+fn worker_unresponsive(state: &Arc<PoolState>, index: usize) {
+    let found = WORKERS.with(|w| {
+        w.borrow().get(&state.id()).map(|p| {
+            let worker = p.workers[index].worker.clone();
+            (worker, p.config.clone())
+        })
+    });
+    if let Some((worker, config)) = found {
+        let err = WorkerError {
+            index,
+            name: config.name(index),
+            message: "worker stopped responding".into(),
+            filename: String::new(),
+            lineno: 0,
+            colno: 0,
+        };
+        worker_died(
+            &Arc::downgrade(state),
+            &worker,
+            err,
+            config.on_worker_error.as_ref(),
+        );
+    }
+}
+
+/// Runs the tasks of the pool on the current thread, see [`Fallback`].
+///
+/// [`Fallback`]: crate::Fallback
+pub(crate) fn start_fallback(
+    state: &Arc<PoolState>,
+    builder: &ThreadPoolBuilder,
+) -> Result<(), PoolError> {
+    // The thread isn't dedicated to the pool, so it only counts as the pool's
+    // worker while the driver is polled.
+    let ctx = WorkerContext {
+        state: state.clone(),
+        index: 0,
+        name: WorkerConfig::new(builder).name(0),
+    };
+    ctx.scope(|| ctx.after_start());
+    let mut driver = Box::pin(PoolState::drive(state.clone(), 0));
+    wasm_bindgen_futures::spawn_local(async move {
+        poll_fn(|cx| ctx.scope(|| driver.as_mut().poll(cx))).await;
+        ctx.scope(|| ctx.before_stop());
+        ctx.state.worker_exited(0, JoinError::PoolShutdown);
+    });
+    Ok(())
+}
+
+/// What a web worker runs, handed over via `worker_entry_point`.
+enum Entry {
+    /// The worker drives its pool.
+    Pool(WorkerContext),
+    /// The worker runs the task and exits afterwards, see [`Launcher`].
+    Thread(String, Task),
+}
+
+/// Entry point invoked by the web worker. The passed pointer will be unconditionally interpreted
+/// as a `Box<Entry>`.
+#[wasm_bindgen(skip_typescript)]
+pub fn worker_entry_point(entry_ptr: u32) {
+    let entry = unsafe { Box::from_raw(entry_ptr as *mut Entry) };
+
+    match *entry {
+        Entry::Pool(ctx) => {
+            debug!("{}: Entry", ctx.name);
+            ctx.enter();
+            let driver = PoolState::drive(ctx.state.clone(), ctx.index);
+            wasm_bindgen_futures::spawn_local(async move {
+                driver.await;
+                info!("{}: Shutting down", ctx.name);
+                ctx.leave();
+                close_worker();
+            });
+        }
+        Entry::Thread(name, task) => {
+            debug!("{}: Entry", name);
+            // Run after reporting back, as the task might block for a long time.
+            wasm_bindgen_futures::spawn_local(async move {
+                task.await;
+                debug!("{}: Exiting", name);
+                close_worker();
+            });
+        }
+    }
+}
+
+/// Starts the worker `index` of the pool. The returned future resolves once
+/// the worker is ready to accept work.
+async fn start(
+    state: &Arc<PoolState>,
+    index: usize,
+    config: &WorkerConfig,
+) -> Result<WorkerHandle, PoolError> {
+    let name = config.name(index);
+    let ctx = WorkerContext {
+        state: state.clone(),
+        index,
+        name: name.clone(),
+    };
+    let worker = launch_worker(
+        Entry::Pool(ctx),
+        &name,
+        config.credentials,
+        config.startup_timeout,
+    )
+    .await?;
+    if !state.has_started(index) {
+        // The worker reported back, but didn't enter this pool. This happens
+        // if it loaded another instance of the wasm module.
+        let e = PoolError::WorkerInitFailed("`worker_entry_point` was not called".into());
+        error!("{}: {}", name, e);
+        worker.terminate();
+        return Err(e);
+    }
+    Ok(WorkerHandle::new(worker, state, index, config))
+}
+
+/// Starts the workers running blocking closures and dedicated tasks.
+///
+/// These are always started from the thread which created the pool, as
+/// starting a worker needs a responsive event loop, which a blocking thread
+/// doesn't have. Also, a worker started by a pool worker would be nested in
+/// it, and die along with it without any notice. The handles of the workers
+/// stay on that thread, so they can be terminated along with the pool.
+pub(crate) struct Launcher {
+    pub(crate) name_prefix: String,
+    tx: mpsc::UnboundedSender<Request>,
+}
+
+/// A message to the thread which created the pool, see [`Launcher`].
+enum Request {
+    Launch(Launch),
+    Terminate,
+}
+
+struct Launch {
+    name: String,
+    task: Task,
+    on_error: Box<dyn FnOnce(JoinError) + Send>,
+}
+
+impl Launcher {
+    /// Must be called on the thread which created the pool, which then
+    /// starts the requested workers.
+    pub(crate) fn new(builder: &ThreadPoolBuilder) -> Self {
+        let (tx, rx) = mpsc::unbounded();
+        let credentials = builder.credentials;
+        let timeout = builder.startup_timeout;
+        let threads = Rc::new(RefCell::new(Threads::default()));
+        // Ends once the pool, and with it the sender, is dropped.
+        wasm_bindgen_futures::spawn_local(rx.for_each_concurrent(None, move |req| {
+            let threads = threads.clone();
+            async move {
+                match req {
+                    Request::Launch(l) => spawn_thread(l, credentials, timeout, &threads).await,
+                    Request::Terminate => threads.borrow_mut().terminate(),
+                }
+            }
+        }));
+        Self {
+            name_prefix: builder.name_prefix.clone(),
+            tx,
+        }
+    }
+
+    /// Starts a worker which runs `task` and exits afterwards. `on_error` is
+    /// called with [`JoinError::WorkerDied`] if the worker couldn't be
+    /// started or crashed, and with [`JoinError::PoolShutdown`] if it was
+    /// terminated. This never blocks, so it can be called from any thread.
+    pub(crate) fn spawn(
+        &self,
+        name: String,
+        task: Task,
+        on_error: Box<dyn FnOnce(JoinError) + Send>,
+    ) {
+        let launch = Launch {
+            name,
+            task,
+            on_error,
+        };
+        if let Err(e) = self.tx.unbounded_send(Request::Launch(launch)) {
+            if let Request::Launch(launch) = e.into_inner() {
+                error!("{}: The thread which created the pool is gone", launch.name);
+                (launch.on_error)(JoinError::WorkerDied);
+            }
+        }
+    }
+
+    /// Terminates all workers started so far, also ones which are still
+    /// starting up.
+    pub(crate) fn terminate(&self) {
+        let _ = self.tx.unbounded_send(Request::Terminate);
+    }
+}
+
+/// The workers started by a [`Launcher`] which are still running, lives on
+/// the thread which created the pool.
+#[derive(Default)]
+struct Threads {
+    terminated: bool,
+    next_id: usize,
+    /// Used to terminate the workers, keyed by an id.
+    running: HashMap<usize, oneshot::Sender<()>>,
+}
+
+impl Threads {
+    /// Registers a worker. Returns `None` if the launcher was terminated
+    /// meanwhile.
+    fn add(&mut self, stop: oneshot::Sender<()>) -> Option<usize> {
+        if self.terminated {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.running.insert(id, stop);
+        Some(id)
+    }
+
+    fn terminate(&mut self) {
+        self.terminated = true;
+        for (_, stop) in self.running.drain() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+/// How a worker started by a [`Launcher`] ended.
+enum Exit {
+    Completed,
+    Crashed(String),
+    Terminated,
+}
+
+/// Starts a web worker which polls the task on its event loop, and exits
+/// once the task completes. Resolves once the worker exited, crashed or was
+/// terminated; `on_error` is called on this thread in the latter cases.
+async fn spawn_thread(
+    launch: Launch,
+    credentials: Option<RequestCredentials>,
+    timeout: Duration,
+    threads: &RefCell<Threads>,
+) {
+    let Launch {
+        name,
+        task,
+        on_error,
+    } = launch;
+    let (done_tx, done_rx) = oneshot::channel::<()>();
+    let task = Box::pin(async move {
+        task.await;
+        let _ = done_tx.send(());
+    });
+    let entry = Entry::Thread(name.clone(), task);
+    let worker = match launch_worker(entry, &name, credentials, timeout).await {
+        Ok(worker) => worker,
+        Err(e) => {
+            error!("{}: {}", name, e);
+            return on_error(JoinError::WorkerDied);
+        }
+    };
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let id = match threads.borrow_mut().add(stop_tx) {
+        Some(id) => id,
+        None => {
+            worker.terminate();
+            return on_error(JoinError::PoolShutdown);
+        }
+    };
+    let (err_tx, err_rx) = oneshot::channel();
+    let mut err_tx = Some(err_tx);
+    let handler = Closure::wrap(Box::new(move |ev: JsValue| {
+        if let Some(tx) = err_tx.take() {
+            let _ = tx.send(error_message(&ev));
+        }
+    }) as Box<dyn FnMut(JsValue)>);
+    worker.set_onerror(Some(handler.as_ref().unchecked_ref()));
+    let exit = select_all(vec![
+        done_rx.map(|_| Exit::Completed).boxed_local(),
+        err_rx
+            .map(|r| r.map_or(Exit::Completed, Exit::Crashed))
+            .boxed_local(),
+        stop_rx.map(|_| Exit::Terminated).boxed_local(),
+    ])
+    .await
+    .0;
+    match exit {
+        Exit::Completed => {}
+        Exit::Crashed(message) => {
+            error!("{}: {}", name, message);
+            worker.terminate();
+            on_error(JoinError::WorkerDied);
+        }
+        Exit::Terminated => {
+            info!("{}: Terminated", name);
+            worker.terminate();
+            on_error(JoinError::PoolShutdown);
+        }
+    }
+    // The handler is dropped once the worker is gone.
+    worker.set_onerror(None);
+    threads.borrow_mut().running.remove(&id);
+}
+
+/// Starts a web worker, which is handed `entry`. The returned future resolves
+/// once the worker has called `worker_entry_point`.
+async fn launch_worker(
+    entry: Entry,
+    name: &str,
+    credentials: Option<RequestCredentials>,
+    timeout: Duration,
+) -> Result<PoolWorker, PoolError> {
+    let mut opts = WorkerOptions::new();
+    opts.type_(WorkerType::Module);
+    opts.name(name);
+    if let Some(credentials) = credentials {
+        opts.credentials(credentials);
+    }
+
+    // With a worker spun up send it the module/memory so it can start
+    // instantiating the wasm module. Later it might receive further
+    // messages about code to run on the wasm module.
+    let ptr = Box::into_raw(Box::new(entry));
+    let started = JsFuture::from(start_worker(
+        wasm_bindgen::module(),
+        wasm_bindgen::memory(),
+        JsValue::from(ptr as u32),
+        opts,
+        LoaderHelper {},
+    ))
+    .await
+    .map_err(|e| {
+        // The worker never got hold of the entry.
+        drop(unsafe { Box::from_raw(ptr) });
+        start_error(e)
+    })?
+    .unchecked_into::<StartedWorker>();
+    let worker = started.worker();
+    let ready = JsFuture::from(started.ready());
+    let res = match select(ready, Box::pin(sleep(timeout))).await {
+        Either::Left((Ok(_), _)) => Ok(()),
+        Either::Left((Err(e), _)) => Err(ready_error(e)),
+        Either::Right(_) => Err(PoolError::StartupTimeout),
+    };
+    if let Err(e) = res {
+        error!("{}: {}", name, e);
+        worker.terminate();
+        return Err(e);
+    }
+    Ok(worker)
+}
+
+/// Classifies an error with which `startWorker` rejected.
+fn start_error(err: JsValue) -> PoolError {
+    match get(&err, "name").and_then(|v| v.as_string()) {
+        // Sharing the wasm memory failed.
+        Some(name) if name == "DataCloneError" => {
+            if cross_origin_isolated() {
+                PoolError::SharedMemoryUnavailable
+            } else {
+                PoolError::NotCrossOriginIsolated
+            }
+        }
+        _ => PoolError::WorkerScriptLoadFailed {
+            url: worker_url(),
+            message: error_message(&err),
+        },
+    }
+}
+
+/// Classifies an error with which `workerReady` rejected.
+fn ready_error(err: JsValue) -> PoolError {
+    let string = |key: &str| get(&err, key).and_then(|v| v.as_string());
+    if let Some(url) = string("loadFailed") {
+        PoolError::WorkerScriptLoadFailed {
+            url,
+            message: string("message").unwrap_or_default(),
+        }
+    } else if string("message").is_some() {
+        PoolError::WorkerInitFailed(error_message(&err))
+    } else {
+        // A plain `Event` is dispatched, if the worker script itself
+        // couldn't be loaded.
+        PoolError::WorkerScriptLoadFailed {
+            url: worker_url(),
+            message: "failed to load worker script".into(),
+        }
+    }
+}
+
+/// Extracts a human readable message from an error thrown by a worker.
+/// Errors are inspected by their properties, as `ErrorEvent` and friends
+/// don't exist on Node.js.
+fn error_message(err: &JsValue) -> String {
+    get(err, "message")
+        .and_then(|v| v.as_string())
+        .unwrap_or_else(|| format!("{:?}", err))
+}
+
+fn get(target: &JsValue, key: &str) -> Option<JsValue> {
+    js_sys::Reflect::get(target, &key.into())
+        .ok()
+        .filter(|v| !v.is_undefined())
+}
+
+/// Keeps the handles of the started `workers` of a pool on this thread.
+fn register(state: &Arc<PoolState>, config: Rc<WorkerConfig>, workers: Vec<WorkerHandle>) {
+    WORKERS.with(|w| {
+        let mut w = w.borrow_mut();
+        // Forget about pools which are gone.
+        w.retain(|_, p| p.state.upgrade().map_or(false, |s| !s.all_exited()));
+        w.insert(
+            state.id(),
+            PoolWorkers {
+                state: Arc::downgrade(state),
+                config,
+                workers,
+                restarts: RestartHistory::default(),
+            },
+        );
+    });
+}
+
+/// Drops the handles of the workers of a pool, once they have exited.
+pub(crate) fn forget(state: &PoolState) {
+    WORKERS.with(|w| w.borrow_mut().remove(&state.id()));
+}
+
+/// Terminates all workers of a pool via `Worker.terminate()`. This is a
+/// no-op if the pool was not created on this thread.
+pub(crate) fn terminate(state: &PoolState) {
+    if let Some(p) = WORKERS.with(|w| w.borrow_mut().remove(&state.id())) {
+        for (idx, handle) in p.workers.into_iter().enumerate() {
+            handle.worker.terminate();
+            state.worker_exited(idx, JoinError::PoolShutdown);
+        }
+    }
+}
+
+fn may_restart(pool_id: usize) -> bool {
+    WORKERS.with(|w| {
+        w.borrow_mut()
+            .get_mut(&pool_id)
+            .map_or(false, |p| p.may_restart())
+    })
+}
+
+/// Replaces the crashed worker `index` with a fresh one.
+async fn restart(state: Arc<PoolState>, index: usize) {
+    let config = match WORKERS.with(|w| w.borrow().get(&state.id()).map(|p| p.config.clone())) {
+        Some(config) => config,
+        None => return,
+    };
+    state.worker_restarting(index);
+    match start(&state, index, &config).await {
+        Ok(handle) => {
+            let orphan = WORKERS.with(|w| match w.borrow_mut().get_mut(&state.id()) {
+                Some(p) => {
+                    p.workers[index] = handle;
+                    None
+                }
+                None => Some(handle),
+            });
+            if let Some(handle) = orphan {
+                // The pool was terminated in the meantime.
+                handle.worker.terminate();
+                state.worker_exited(index, JoinError::PoolShutdown);
+            } else {
+                info!("{}: Restarted", config.name(index));
+            }
+        }
+        Err(e) => {
+            error!("{}: Restart failed: {:?}", config.name(index), e);
+            state.worker_exited(index, JoinError::WorkerDied);
+        }
+    }
+}
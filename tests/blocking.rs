@@ -0,0 +1,84 @@
+//! Tests of `ThreadPool::spawn_blocking` on native targets.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use futures::executor::block_on;
+use futures::future::join_all;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use wasm_futures_executor::{JoinError, ThreadPool};
+
+mod common;
+use common::{pool, wait_for};
+
+#[test]
+fn grows_up_to_max_threads() {
+    let pool = block_on(
+        ThreadPool::builder()
+            .pool_size(1)
+            .max_blocking_threads(2)
+            .create(),
+    )
+    .unwrap();
+    let running = Arc::new(AtomicUsize::new(0));
+    let release = Arc::new(AtomicBool::new(false));
+    let handles = (0..4)
+        .map(|i| {
+            let (running, release) = (running.clone(), release.clone());
+            pool.spawn_blocking(move || {
+                running.fetch_add(1, Ordering::SeqCst);
+                while !release.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                running.fetch_sub(1, Ordering::SeqCst);
+                i
+            })
+        })
+        .collect::<Vec<_>>();
+    wait_for(|| running.load(Ordering::SeqCst) == 2);
+    // The other closures wait for a free thread.
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(running.load(Ordering::SeqCst), 2);
+    release.store(true, Ordering::SeqCst);
+    let results = block_on(join_all(handles));
+    assert_eq!(results, vec![Ok(0), Ok(1), Ok(2), Ok(3)]);
+}
+
+#[test]
+fn idle_threads_exit() {
+    let pool = block_on(
+        ThreadPool::builder()
+            .pool_size(1)
+            .blocking_keep_alive(Duration::from_millis(50))
+            .create(),
+    )
+    .unwrap();
+    let thread_id = || block_on(pool.spawn_blocking(|| thread::current().id())).unwrap();
+    let first = thread_id();
+    // Give the thread time to go idle.
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(thread_id(), first);
+    thread::sleep(Duration::from_millis(200));
+    assert_ne!(thread_id(), first);
+}
+
+#[test]
+fn closure_may_block_on() {
+    let pool = pool(1);
+    let p = pool.clone();
+    let handle = pool.spawn_blocking(move || block_on(p.spawn(async { 7 })));
+    assert_eq!(block_on(handle), Ok(Ok(7)));
+}
+
+#[test]
+fn spawn_after_shutdown() {
+    let pool = pool(1);
+    let other = pool.clone();
+    block_on(pool.shutdown());
+    assert_eq!(
+        block_on(other.spawn_blocking(|| 1)),
+        Err(JoinError::PoolShutdown)
+    );
+}
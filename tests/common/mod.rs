@@ -0,0 +1,48 @@
+//! Helpers shared by the native tests.
+
+// Not every test uses every helper.
+#![allow(dead_code)]
+
+use futures::executor::block_on;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use wasm_futures_executor::ThreadPool;
+
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn pool(size: usize) -> ThreadPool {
+    block_on(ThreadPool::builder().pool_size(size).create()).unwrap()
+}
+
+pub fn pool_with_capacity(size: usize, capacity: usize) -> ThreadPool {
+    block_on(
+        ThreadPool::builder()
+            .pool_size(size)
+            .queue_capacity(capacity)
+            .create(),
+    )
+    .unwrap()
+}
+
+/// Occupies a worker of `pool` until the returned sender is dropped. Waits
+/// until the worker picked up the blocking task.
+pub fn block_worker(pool: &ThreadPool) -> mpsc::Sender<()> {
+    let (started_tx, started_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    pool.spawn_ok(async move {
+        started_tx.send(()).unwrap();
+        let _ = release_rx.recv();
+    });
+    started_rx.recv_timeout(TIMEOUT).unwrap();
+    release_tx
+}
+
+/// Waits until `cond` holds, panicking after [`TIMEOUT`].
+pub fn wait_for(cond: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !cond() {
+        assert!(start.elapsed() < TIMEOUT, "condition not met in time");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,40 @@
+//! Tests of the global pool on native targets. The global pool is shared by
+//! the whole process, so everything is tested in order in a single test.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use futures::executor::block_on;
+use wasm_futures_executor::{GlobalError, ThreadPool};
+
+#[test]
+fn global_pool() {
+    assert_eq!(
+        wasm_futures_executor::try_spawn(async {}).err(),
+        Some(GlobalError::Uninitialized)
+    );
+    assert_eq!(
+        wasm_futures_executor::try_spawn_ok(async {}),
+        Err(GlobalError::Uninitialized)
+    );
+
+    let mut builder = ThreadPool::builder();
+    builder.pool_size(2);
+    assert_eq!(
+        block_on(wasm_futures_executor::init_global(&mut builder)),
+        Ok(())
+    );
+    assert_eq!(
+        block_on(wasm_futures_executor::init_global(&mut builder)),
+        Err(GlobalError::AlreadyInitialized)
+    );
+
+    let handle = wasm_futures_executor::spawn(async { 6 * 7 });
+    assert_eq!(block_on(handle), Ok(42));
+    let handle = wasm_futures_executor::try_spawn(async { 7 }).unwrap();
+    assert_eq!(block_on(handle), Ok(7));
+    assert_eq!(wasm_futures_executor::try_spawn_ok(async {}), Ok(()));
+    // Spawning from within a task.
+    let handle =
+        wasm_futures_executor::spawn(async { wasm_futures_executor::spawn(async { 1 }).await });
+    assert_eq!(block_on(handle), Ok(Ok(1)));
+}
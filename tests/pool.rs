@@ -0,0 +1,257 @@
+//! Tests of the pool on native targets, where workers are plain threads.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use futures::executor::block_on;
+use futures::future::join_all;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use wasm_futures_executor::{
+    current_worker, Fallback, JoinError, JoinHandle, Priority, RestartPolicy, ThreadPool,
+};
+
+mod common;
+use common::{block_worker, pool, wait_for, TIMEOUT};
+
+#[test]
+fn spawn_and_join() {
+    let pool = pool(2);
+    let handles = (0..50).map(|i| pool.spawn(async move { i * 2 }));
+    let results = block_on(join_all(handles));
+    let expected = (0..50).map(|i| Ok(i * 2)).collect::<Vec<_>>();
+    assert_eq!(results, expected);
+}
+
+#[test]
+fn finished_after_completion() {
+    let pool = pool(1);
+    let mut handle = pool.spawn(async { 42 });
+    assert_eq!(block_on(&mut handle), Ok(42));
+    assert!(handle.is_finished());
+}
+
+#[test]
+fn abort_queued_task() {
+    let pool = pool(1);
+    let release = block_worker(&pool);
+    let ran = Arc::new(AtomicBool::new(false));
+    let r = ran.clone();
+    let mut handle = pool.spawn(async move { r.store(true, Ordering::SeqCst) });
+    assert!(!handle.is_finished());
+    handle.abort();
+    drop(release);
+    assert_eq!(block_on(&mut handle), Err(JoinError::Cancelled));
+    assert!(handle.is_finished());
+    assert!(!ran.load(Ordering::SeqCst));
+}
+
+#[test]
+fn panicking_task() {
+    let pool = pool(1);
+    let handle: JoinHandle<()> = pool.spawn(async { panic!("boom") });
+    assert_eq!(block_on(handle), Err(JoinError::Panicked));
+    // The worker survived.
+    assert_eq!(block_on(pool.spawn(async { 7 })), Ok(7));
+    assert_eq!(pool.live_workers(), 1);
+}
+
+#[test]
+fn shutdown_drains_tasks() {
+    let pool = pool(2);
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..50 {
+        let counter = counter.clone();
+        pool.spawn_ok(async move {
+            thread::sleep(Duration::from_millis(1));
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    let other = pool.clone();
+    block_on(pool.shutdown());
+    assert_eq!(counter.load(Ordering::SeqCst), 50);
+    assert_eq!(other.live_workers(), 0);
+}
+
+#[test]
+fn terminate_fails_queued_tasks() {
+    let pool = pool(1);
+    let release = block_worker(&pool);
+    let handle = pool.spawn(async { 1 });
+    pool.terminate();
+    assert_eq!(block_on(handle), Err(JoinError::PoolShutdown));
+    drop(release);
+}
+
+/// Panics once the panic payload is dropped, so the panic escapes the
+/// `catch_unwind` of the task and takes down the worker.
+struct PanicOnDrop;
+
+impl Drop for PanicOnDrop {
+    fn drop(&mut self) {
+        panic!("payload dropped");
+    }
+}
+
+#[test]
+fn crashed_worker_is_restarted() {
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let pool = block_on(
+        ThreadPool::builder()
+            .pool_size(1)
+            .restart_policy(RestartPolicy::Always)
+            .on_worker_error(move |err| tx.lock().unwrap().send(err).unwrap())
+            .create(),
+    )
+    .unwrap();
+    let handle: JoinHandle<()> = pool.spawn(async { std::panic::panic_any(PanicOnDrop) });
+    assert_eq!(block_on(handle), Err(JoinError::WorkerDied));
+    let err = rx.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(err.index, 0);
+    assert_eq!(pool.restarts(), 1);
+    assert_eq!(block_on(pool.spawn(async { 7 })), Ok(7));
+}
+
+#[test]
+fn hooks_run_once_per_worker() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let stopped = Arc::new(AtomicUsize::new(0));
+    let (s, t) = (started.clone(), stopped.clone());
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let pool = block_on(
+        ThreadPool::builder()
+            .pool_size(2)
+            .restart_policy(RestartPolicy::Always)
+            .after_start(move |_| {
+                s.fetch_add(1, Ordering::SeqCst);
+            })
+            .before_stop(move |_| {
+                t.fetch_add(1, Ordering::SeqCst);
+            })
+            .on_worker_error(move |err| tx.lock().unwrap().send(err).unwrap())
+            .create(),
+    )
+    .unwrap();
+    wait_for(|| started.load(Ordering::SeqCst) == 2);
+    let handle: JoinHandle<()> = pool.spawn(async { std::panic::panic_any(PanicOnDrop) });
+    assert_eq!(block_on(handle), Err(JoinError::WorkerDied));
+    rx.recv_timeout(TIMEOUT).unwrap();
+    wait_for(|| started.load(Ordering::SeqCst) == 3);
+    block_on(pool.shutdown());
+    assert_eq!(started.load(Ordering::SeqCst), 3);
+    // Not called for the crashed worker.
+    assert_eq!(stopped.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn current_pool() {
+    assert!(ThreadPool::current().is_none());
+    let pool = pool(1);
+    let id = pool.id();
+    let (started_tx, started_rx) = mpsc::channel();
+    let (dropped_tx, dropped_rx) = mpsc::channel::<()>();
+    let handle = pool.spawn(async move {
+        let current = ThreadPool::current().unwrap();
+        started_tx.send(current.id()).unwrap();
+        // Wait until the other handle is gone.
+        dropped_rx.recv_timeout(TIMEOUT).unwrap();
+        current.spawn(async { 5 }).await
+    });
+    assert_eq!(started_rx.recv_timeout(TIMEOUT), Ok(id));
+    // `current` keeps the pool alive on its own.
+    drop(pool);
+    dropped_tx.send(()).unwrap();
+    assert_eq!(block_on(handle), Ok(Ok(5)));
+}
+
+#[test]
+fn fallback_pool() {
+    let pool = block_on(
+        ThreadPool::builder()
+            .pool_size(4)
+            .fallback(Fallback::Always)
+            .create(),
+    )
+    .unwrap();
+    assert!(pool.is_fallback());
+    assert_eq!(pool.live_workers(), 1);
+    let handles = (0..10).map(|i| pool.spawn(async move { i * 2 }));
+    let results = block_on(join_all(handles));
+    assert_eq!(results, (0..10).map(|i| Ok(i * 2)).collect::<Vec<_>>());
+}
+
+#[test]
+fn higher_priority_first() {
+    let pool = pool(1);
+    let release = block_worker(&pool);
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let handles = [Priority::Low, Priority::High]
+        .iter()
+        .flat_map(|&priority| (0..8).map(move |_| priority))
+        .map(|priority| {
+            let order = order.clone();
+            pool.spawn_with_priority(priority, async move {
+                order.lock().unwrap().push(priority);
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(release);
+    block_on(join_all(handles));
+    let order = order.lock().unwrap();
+    // A low priority task may go first once, so it doesn't starve.
+    let high = order[..8].iter().filter(|&&p| p == Priority::High).count();
+    assert!(high >= 7, "{:?}", order);
+}
+
+/// Keeps spawning high priority tasks, until `done` is set or `rounds`
+/// reaches the limit.
+fn spawn_high(pool: ThreadPool, done: Arc<AtomicBool>, rounds: Arc<AtomicUsize>) {
+    if done.load(Ordering::SeqCst) || rounds.fetch_add(1, Ordering::SeqCst) >= 1000 {
+        return;
+    }
+    let p = pool.clone();
+    pool.spawn_with_priority(Priority::High, async move { spawn_high(p, done, rounds) });
+}
+
+#[test]
+fn low_priority_does_not_starve() {
+    let pool = pool(1);
+    let release = block_worker(&pool);
+    let done = Arc::new(AtomicBool::new(false));
+    let rounds = Arc::new(AtomicUsize::new(0));
+    spawn_high(pool.clone(), done.clone(), rounds.clone());
+    let low = pool.spawn_with_priority(Priority::Low, async move {
+        done.store(true, Ordering::SeqCst);
+    });
+    drop(release);
+    assert_eq!(block_on(low), Ok(()));
+    assert!(rounds.load(Ordering::SeqCst) < 100);
+}
+
+#[test]
+fn idle_worker_steals() {
+    let pool = pool(2);
+    let p = pool.clone();
+    let handle = pool.spawn(async move {
+        let (tx, rx) = mpsc::channel();
+        // Queued locally, and this worker is blocked until it ran.
+        p.spawn_ok(async move { tx.send(current_worker().unwrap().index).unwrap() });
+        let parent = current_worker().unwrap().index;
+        (parent, rx.recv_timeout(TIMEOUT).ok())
+    });
+    let (parent, child) = block_on(handle).unwrap();
+    assert_eq!(child, Some(1 - parent));
+}
+
+#[test]
+fn dedicated_task() {
+    let pool = pool(1);
+    let p = pool.clone();
+    // Runs on a thread of its own, which isn't a worker of the pool.
+    let handle = pool.spawn_dedicated(async move { (current_worker(), p.live_workers()) });
+    assert_eq!(block_on(handle), Ok((None, 1)));
+    assert_eq!(pool.live_workers(), 1);
+}
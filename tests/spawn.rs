@@ -0,0 +1,93 @@
+//! Tests of the lock-free spawning paths on native targets.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use futures::executor::block_on;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use wasm_futures_executor::{SpawnError, ThreadPool};
+
+mod common;
+use common::{block_worker, pool_with_capacity, wait_for};
+
+const THREADS: usize = 4;
+const TASKS_PER_THREAD: usize = 250;
+const PARENTS: usize = THREADS * TASKS_PER_THREAD;
+
+/// Spawns tasks from several threads at once, and from within the tasks, on
+/// a pool with a small queue. Every task must run exactly once.
+#[test]
+fn every_task_runs_once() {
+    let pool = pool_with_capacity(4, 16);
+    // One counter per parent task, followed by one per child task.
+    let runs = Arc::new(
+        (0..2 * PARENTS)
+            .map(|_| AtomicUsize::new(0))
+            .collect::<Vec<_>>(),
+    );
+    let threads = (0..THREADS)
+        .map(|t| {
+            let (pool, runs) = (pool.clone(), runs.clone());
+            thread::spawn(move || {
+                for i in t * TASKS_PER_THREAD..(t + 1) * TASKS_PER_THREAD {
+                    let spawn = || pool.try_spawn_ok(run_parent(pool.clone(), runs.clone(), i));
+                    // Retry until there is room in the queue.
+                    while let Err(e) = spawn() {
+                        assert_eq!(e, SpawnError::Full);
+                        thread::yield_now();
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for t in threads {
+        t.join().unwrap();
+    }
+    wait_for(|| runs.iter().map(|r| r.load(Ordering::SeqCst)).sum::<usize>() == 2 * PARENTS);
+    block_on(pool.shutdown());
+    for (i, r) in runs.iter().enumerate() {
+        assert_eq!(r.load(Ordering::SeqCst), 1, "task {}", i);
+    }
+}
+
+/// Spawns a child task from within the pool, falling back to waiting for
+/// room in the queue if it's full.
+async fn run_parent(pool: ThreadPool, runs: Arc<Vec<AtomicUsize>>, i: usize) {
+    runs[i].fetch_add(1, Ordering::SeqCst);
+    if let Err(e) = pool.try_spawn_ok(run_child(runs.clone(), i)) {
+        assert_eq!(e, SpawnError::Full);
+        pool.spawn_async(run_child(runs, i)).await.unwrap();
+    }
+}
+
+async fn run_child(runs: Arc<Vec<AtomicUsize>>, i: usize) {
+    runs[PARENTS + i].fetch_add(1, Ordering::SeqCst);
+}
+
+#[test]
+fn full_queue() {
+    let pool = pool_with_capacity(1, 4);
+    let release = block_worker(&pool);
+    for _ in 0..4 {
+        assert_eq!(pool.try_spawn_ok(async {}), Ok(()));
+    }
+    assert_eq!(pool.try_spawn_ok(async {}), Err(SpawnError::Full));
+    assert_eq!(pool.try_spawn(async {}).err(), Some(SpawnError::Full));
+    drop(release);
+    // Waits until one of the queued tasks was picked up.
+    assert_eq!(block_on(pool.spawn_async(async {})), Ok(()));
+}
+
+#[test]
+fn shut_down_pool() {
+    let pool = pool_with_capacity(1, 4);
+    let other = pool.clone();
+    block_on(pool.shutdown());
+    assert_eq!(other.try_spawn_ok(async {}), Err(SpawnError::Shutdown));
+    assert_eq!(other.try_spawn(async {}).err(), Some(SpawnError::Shutdown));
+    assert_eq!(
+        block_on(other.spawn_async(async {})),
+        Err(SpawnError::Shutdown)
+    );
+}
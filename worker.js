@@ -2,57 +2,195 @@
 // First: Expose a function to start a web worker. This function must
 // not be inlined into the Rust lib, as otherwise bundlers could not
 // bundle it -- huh.
-export function startWorker(module, memory, state, opts, helper) {
-    const worker = new Worker(new URL('./worker.js',
-        import.meta.url), opts);
-    worker.postMessage([module, memory, state, helper.mainJS()]);
+// On Node.js, `worker_threads` are used instead of web workers.
+const isNode = typeof process === 'object' && process.versions != null &&
+    process.versions.node != null;
 
+// Resolves to `{ worker, ready }`, where `ready` is the promise returned
+// by `workerReady`. Its handlers are attached before the worker gets its
+// first message, so no event is missed.
+export async function startWorker(module, memory, state, opts, helper) {
+    // Webpack only picks up workers created exactly like this.
+    const worker = isNode ? await startNodeWorker(opts) :
+        new Worker(new URL('./worker.js', import.meta.url), opts);
+    const ready = workerReady(worker);
+    // Rejections are handled once the caller awaits `ready`.
+    ready.catch(() => {});
+    try {
+        worker.postMessage([module, memory, state, helper.mainJS()]);
+    } catch (err) {
+        // Most likely, the memory couldn't be shared.
+        worker.terminate();
+        throw err;
+    }
+    return { worker, ready };
+}
+
+async function startNodeWorker(opts) {
+    const { Worker } = await import(/* webpackIgnore: true */ 'worker_threads');
+    const worker = new Worker(new URL('./worker.js', import.meta.url), {
+        name: opts.name,
+        workerData: { wasmFuturesExecutor: true },
+    });
+    return new NodeWorker(worker);
+}
+
+// Makes a `worker_threads.Worker` look like a web `Worker`, as far as
+// this crate is concerned.
+class NodeWorker {
+    constructor(worker) {
+        this.worker = worker;
+        this.onmessage = null;
+        this.onerror = null;
+        worker.on('message', data => this.onmessage && this.onmessage({ data }));
+        worker.on('error', err => this.onerror && this.onerror({
+            message: String(err && err.message || err),
+            filename: '',
+            lineno: 0,
+            colno: 0,
+        }));
+    }
+
+    postMessage(msg) {
+        this.worker.postMessage(msg);
+    }
+
+    terminate() {
+        this.worker.terminate();
+    }
+}
+
+export function workerUrl() {
+    return new URL('./worker.js', import.meta.url).href;
+}
+
+// Feature detection for module workers: the `type` option is only read
+// by browsers which support it.
+export function supportsModuleWorkers() {
+    if (isNode) return true;
+    if (typeof Worker === 'undefined') return false;
+    let supported = false;
+    const tester = {
+        get type() { supported = true; return 'module'; }
+    };
+    try {
+        new Worker('blob://', tester).terminate();
+    } catch (e) {}
+    return supported;
+}
+
+// Set by `loadHardwareConcurrency` on Node.js before v21, which doesn't
+// have `navigator`.
+let nodeParallelism = 0;
+
+export function hardwareConcurrency() {
+    if (typeof navigator !== 'undefined' && navigator.hardwareConcurrency) {
+        return navigator.hardwareConcurrency;
+    }
+    return nodeParallelism;
+}
+
+// Resolves once `hardwareConcurrency` is known. The `os` module can only
+// be imported asynchronously from an ES module.
+export async function loadHardwareConcurrency() {
+    if (!isNode || hardwareConcurrency()) return;
+    const os = await import(/* webpackIgnore: true */ 'os');
+    nodeParallelism = os.availableParallelism ?
+        os.availableParallelism() : os.cpus().length;
+}
+
+// Resolves once the worker has called `worker_entry_point`. Rejects with
+// `{ loadFailed, message }` if the worker couldn't import the main module,
+// or with the error event otherwise.
+function workerReady(worker) {
     return new Promise((res, rej) => {
       worker.onmessage = ev => {
         if (ev.data === 'started') res();
+        else if (ev.data && ev.data.loadFailed) rej(ev.data);
       };
       worker.onerror = rej;
     });
 }
 
+// Ends the current worker.
+export function closeWorker() {
+    if (isNode) process.exit();
+    else close();
+}
+
 // Second: Entry script for the actual web worker.
-if ('WorkerGlobalScope' in self &&
+if (isNode) {
+    import(/* webpackIgnore: true */ 'worker_threads').then(
+        ({ parentPort, workerData }) => {
+            // Don't interfere with workers not started by this crate.
+            if (workerData && workerData.wasmFuturesExecutor) {
+                parentPort.once('message', data =>
+                    initWorker(data, msg => parentPort.postMessage(msg)));
+            }
+        });
+} else if ('WorkerGlobalScope' in self &&
     self instanceof WorkerGlobalScope) {
 
-    // Initialize wasm module, and memory. `state` is the shared state,
-    // to be used with `worker_entry_point`.
-    self.onmessage = async event => {
-        let [module, memory, state, mainJS] = event.data;
-        // This crate only works with bundling via webpack or not
-        // using a bundler at all:
-        // When bundling with webpack, this file is relative to the wasm
-        // module file (or package.json) located in `../..` generated by
-        // wasm-pack.
-        // When using it without any bundlers, the module that
-        // provided the `helper` object below is loaded; in other words
-        // the main wasm module.
-        const importFrom = (typeof __webpack_require__ === 'function') ? import('../..') : import(mainJS);
-        try {
-            const {
-                default: init,
-                worker_entry_point
-            } = await importFrom;
-            await init(module, memory);
-
-            worker_entry_point(state);
-            postMessage('started');
-            // There shouldn't be any additional messages after the first.
-            self.onmessage = event => {
-                console.error("Unexpected message", event);
-            }
-        } catch (err) {
-            // Propagate to main `onerror`:
-            setTimeout(() => {
-                throw err;
-                //Terminate the worker
-                close();
-            });
-            throw err;
+    self.onmessage = event => {
+        initWorker(event.data, msg => postMessage(msg));
+        // There shouldn't be any additional messages after the first.
+        self.onmessage = event => {
+            console.error("Unexpected message", event);
         }
     }
 }
+
+// Initialize wasm module, and memory. `state` is the shared state,
+// to be used with `worker_entry_point`.
+async function initWorker([module, memory, state, mainJS], post) {
+    // Tasks are run from promise callbacks, so a wasm trap within a task
+    // surfaces as an unhandled rejection instead of an uncaught error.
+    // Rethrow it, so `onerror` fires on the `Worker` object. On Node.js,
+    // unhandled rejections already end the worker with an 'error' event.
+    if (!isNode) {
+        self.addEventListener('unhandledrejection', event => {
+            throw event.reason;
+        });
+    }
+    // This crate only works with bundling via webpack or not
+    // using a bundler at all:
+    // When bundling with webpack, this file is relative to the wasm
+    // module file (or package.json) located in `../..` generated by
+    // wasm-pack.
+    // When using it without any bundlers, the module that
+    // provided the `helper` object below is loaded; in other words
+    // the main wasm module.
+    const bundled = typeof __webpack_require__ === 'function';
+    // Lets `is_pool_worker` tell apart workers already while the start
+    // function runs, which may happen on import when bundled.
+    globalThis.wasmFuturesExecutorWorker = true;
+    let imported;
+    try {
+        imported = await (bundled ? import('../..') : import(mainJS));
+    } catch (err) {
+        post({
+            loadFailed: bundled ? '../..' : mainJS,
+            message: String(err),
+        });
+        closeWorker();
+        return;
+    }
+    try {
+        const {
+            default: init,
+            worker_entry_point
+        } = imported;
+        await init(module, memory);
+
+        worker_entry_point(state);
+        post('started');
+    } catch (err) {
+        // Propagate to main `onerror`:
+        setTimeout(() => {
+            throw err;
+            //Terminate the worker
+            closeWorker();
+        });
+        throw err;
+    }
+}